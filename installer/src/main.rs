@@ -1,10 +1,11 @@
 use std::fs;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use reqwest::Client;
 use serde_json::json;
 use std::process::Command;
-use serde_json::Value;
+use std::time::Duration;
+use serde_json::{Map, Value};
 
 const CURRENT_INSTALLER_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -70,19 +71,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         println!("Binary installed to: {}", install_path.display());
 
-        let should_generate_config = prompt_with_default("Do you want to generate a config.json file?", "yes")?
+        let should_generate_config = prompt_with_default("Do you want to generate a config file?", "yes")?
             .to_lowercase();
         
         if should_generate_config.starts_with('y') {
-            println!("Generating config.json...");
-            let config = generate_config()?;
+            println!("Generating config file...");
+            let config = generate_config(&client).await?;
         
-            let config_path = install_path.with_file_name("config.json");
-            fs::write(&config_path, serde_json::to_string_pretty(&config)?)?;
+            let format = prompt_with_default("Config file format (json/toml)", "json")?.to_lowercase();
+            let config_path = canonical_config_path().unwrap_or_else(|| install_path.with_file_name("config.json"));
+            if let Some(parent) = config_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let (config_path, contents) = if format.starts_with('t') {
+                (config_path.with_extension("toml"), toml::to_string_pretty(&config)?)
+            } else {
+                (config_path, serde_json::to_string_pretty(&config)?)
+            };
+            fs::write(&config_path, contents)?;
+
+            #[cfg(target_family = "unix")]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mut perms = fs::metadata(&config_path)?.permissions();
+                perms.set_mode(0o600);
+                fs::set_permissions(&config_path, perms)?;
+            }
+
             println!("Config file created at: {}", config_path.display());
         } else {
-            println!("Skipping config.json generation.");
-            println!("Note: You'll need to create a config.json file manually before running the service.");
+            println!("Skipping config file generation.");
+            println!("Note: You'll need to create a config.json or config.toml file manually before running the service.");
         }
 
         let should_install_service = prompt_with_default("Do you want to install an autostart service?", "yes")?
@@ -126,23 +145,101 @@ async fn get_latest_version(client: &Client) -> Result<String, Box<dyn std::erro
         .ok_or_else(|| "No tag_name found in the response".into())
 }
 
-fn generate_config() -> Result<serde_json::Value, io::Error> {
+async fn generate_config(client: &Client) -> Result<serde_json::Value, io::Error> {
     println!("Please enter the following information:");
 
     let audiobookshelf_url = prompt("Audiobookshelf URL (Don't forget to include port if not reverse proxying)")?;
     let audiobookshelf_token = prompt("Audiobookshelf API Key (Find this when clicking on your user in settings)")?;
+    // Keep in sync with the prompts of `audiobookshelf-discord-rpc --init`.
     let default_discord_client_id = "1283070638088650752";
     let discord_client_id = prompt_with_default("Discord Client ID", default_discord_client_id)?;
     let show_chapters = prompt_with_default("Show chapters instead of genres? (yes/no)", "no")?
         .to_lowercase()
         .starts_with('y');
     
-    Ok(json!({
+    let tls = tls_settings(client, &audiobookshelf_url, &audiobookshelf_token).await?;
+
+    let mut config = json!({
         "discord_client_id": discord_client_id,
         "audiobookshelf_url": audiobookshelf_url,
         "audiobookshelf_token": audiobookshelf_token,
         "show_chapters": show_chapters,
-    }))
+    });
+    if let Some(config) = config.as_object_mut() {
+        config.extend(tls);
+    }
+    Ok(config)
+}
+
+/// Tries the login once when the URL uses https. A server with a self-signed
+/// or internal CA certificate can't be reached until one of the TLS options
+/// is set, so they are asked for here. Other failures are left for the
+/// service's own log.
+async fn tls_settings(client: &Client, url: &str, token: &str) -> Result<Map<String, Value>, io::Error> {
+    let mut settings = Map::new();
+    if !url.starts_with("https://") {
+        return Ok(settings);
+    }
+    let result = client
+        .get(format!("{}/api/me", url.trim_end_matches('/')))
+        .bearer_auth(token)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await;
+    let error = match result {
+        Err(e) if is_certificate_error(&e) => e,
+        _ => return Ok(settings),
+    };
+
+    println!("Audiobookshelf's certificate isn't trusted: {}", error);
+    let ca_file = prompt("Path to your CA certificate (PEM) to trust, leave empty to skip")?;
+    if !ca_file.is_empty() {
+        settings.insert("tls_ca_file".to_string(), json!(ca_file));
+    } else if prompt_with_default(
+        "Skip certificate checks instead? Anyone who can intercept the connection could read your API key (yes/no)",
+        "no",
+    )?
+    .to_lowercase()
+    .starts_with('y')
+    {
+        settings.insert("tls_accept_invalid_certs".to_string(), json!(true));
+    }
+    Ok(settings)
+}
+
+/// reqwest has no flag for TLS certificate failures, so the causes are
+/// searched for the word, like the main binary does.
+fn is_certificate_error(error: &reqwest::Error) -> bool {
+    let mut cause: Option<&dyn std::error::Error> = Some(error);
+    while let Some(error) = cause {
+        if error.to_string().to_lowercase().contains("certificate") {
+            return true;
+        }
+        cause = error.source();
+    }
+    false
+}
+
+/// Same location the main binary looks in when it's started without -c.
+fn canonical_config_path() -> Option<PathBuf> {
+    let dir_name = if cfg!(target_os = "windows") {
+        "AudiobookshelfDiscordRPC"
+    } else {
+        "audiobookshelf-discord-rpc"
+    };
+    dirs::config_dir().map(|dir| dir.join(dir_name).join("config.json"))
+}
+
+/// The config the service should use: the canonical one (config.toml
+/// before config.json), unless only an older install's config.json next to
+/// the binary exists.
+fn config_path(install_path: &Path) -> PathBuf {
+    let legacy = install_path.with_file_name("config.json");
+    match canonical_config_path() {
+        Some(canonical) if canonical.with_extension("toml").exists() => canonical.with_extension("toml"),
+        Some(canonical) if canonical.exists() || !legacy.exists() => canonical,
+        _ => legacy,
+    }
 }
 
 fn prompt_with_default(prompt: &str, default: &str) -> Result<String, io::Error> {
@@ -173,7 +270,7 @@ fn create_windows_service(install_path: &PathBuf) -> Result<(), Box<dyn std::err
     let task_name = "AudiobookshelfDiscordRPC";
     let task_program = install_path.display().to_string();
     
-    let config_path_buf = install_path.with_file_name("config.json");
+    let config_path_buf = config_path(install_path);
     let config_path_display = config_path_buf.display();
     
     let task_arguments = format!("-c \"{}\"", config_path_display);
@@ -225,7 +322,7 @@ RestartSec=10
 WantedBy=default.target
 "#,
         install_path.display(),
-        install_path.with_file_name("config.json").display(),
+        config_path(install_path).display(),
     );
 
     let config_dir = PathBuf::from(std::env::var("HOME")?).join(".config").join("systemd").join("user");
@@ -0,0 +1,653 @@
+//! Audiobookshelf API types and requests, and picking which listening
+//! session to show.
+
+use log::{debug, info, warn};
+use reqwest::header::USER_AGENT;
+use reqwest::{Certificate, Client, ClientBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time;
+use url::Url;
+
+use crate::config::{request_timeout, AccountConfig, Config, DEFAULT_CONNECT_TIMEOUT_SECS};
+use crate::error::{redact_request_error, redact_url, AppError};
+
+pub(crate) const SESSIONS_PER_POLL: u32 = 10;
+pub(crate) const MIN_LISTENING_SECS_FOR_SPEED: f64 = 60.0;
+pub(crate) const MAX_REQUEST_RETRIES: u32 = 3;
+pub const RETRY_BASE_DELAY_SECS: u64 = 1;
+/// Idle connections are kept for a few polls so each one doesn't reconnect.
+const POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+const TCP_KEEPALIVE_SECS: u64 = 60;
+pub const USER_AGENT_VALUE: &str = concat!("audiobookshelf-discord-rpc/", env!("CARGO_PKG_VERSION"));
+
+/// The user a token belongs to, from `/api/me`.
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CoverResponse {
+    pub results: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListeningSessionsResponse {
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct Session {
+    pub displayTitle: String,
+    pub displayAuthor: String,
+    pub currentTime: f64,
+    pub duration: f64,
+    pub playbackRate: Option<f64>,
+    pub startTime: Option<f64>,
+    pub timeListening: Option<f64>,
+    pub updatedAt: Option<u64>,
+    pub startedAt: Option<u64>,
+    pub id: Option<String>,
+    pub deviceInfo: Option<DeviceInfo>,
+    pub mediaPlayer: Option<String>,
+    pub mediaType: Option<String>,
+    pub mediaMetadata: MediaMetadata,
+    pub libraryItemId: String,
+    pub libraryId: Option<String>,
+    pub episodeId: Option<String>,
+    pub chapters: Option<Vec<Chapter>>,
+    pub libraryItem: Option<LibraryItem>,
+}
+
+impl Session {
+    /// When the server last heard of the session, in milliseconds since the
+    /// epoch. Falls back to its start for servers that don't send `updatedAt`.
+    pub fn last_update(&self) -> Option<u64> {
+        self.updatedAt.or(self.startedAt)
+    }
+}
+
+/// The server's saved position for an item, from `/api/me/progress/{id}`.
+/// `lastUpdate` is in milliseconds since the epoch.
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct MediaProgress {
+    pub currentTime: f64,
+    pub lastUpdate: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct DeviceInfo {
+    pub deviceName: Option<String>,
+    pub clientName: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model: Option<String>,
+    pub osName: Option<String>,
+    pub browserName: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct MediaMetadata {
+    pub title: Option<String>,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub narrators: Vec<String>,
+    /// The narrators as one string, sent instead of `narrators` by some
+    /// Audiobookshelf versions.
+    pub narratorName: Option<String>,
+    #[serde(default)]
+    pub series: Vec<SeriesInfo>,
+}
+
+impl MediaMetadata {
+    /// The narrators joined with commas, empty when none are known.
+    pub fn narrator(&self) -> String {
+        if self.narrators.is_empty() {
+            self.narratorName.as_deref().unwrap_or_default().trim().to_string()
+        } else {
+            self.narrators.join(", ")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SeriesInfo {
+    pub name: String,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub sequence: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryItem {
+    pub media: Option<Media>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Media {
+    pub chapters: Option<Vec<Chapter>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryItemResponse {
+    pub media: MediaResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MediaResponse {
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+    #[serde(default)]
+    pub episodes: Vec<PodcastEpisode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PodcastEpisode {
+    pub id: String,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub season: Option<String>,
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub episode: Option<String>,
+}
+
+/// Some servers send series sequences and episode numbers as JSON numbers
+/// instead of strings; either way they end up as the text shown.
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(serde_json::Number),
+    }
+
+    Ok(Option::<StringOrNumber>::deserialize(deserializer)?.map(|value| match value {
+        StringOrNumber::String(value) => value,
+        StringOrNumber::Number(value) => value.to_string(),
+    }))
+}
+
+/// Requests against one Audiobookshelf server. The base URL may include a
+/// subpath (e.g. `https://example.com/audiobookshelf/`); a trailing slash is
+/// ignored.
+#[derive(Debug, Clone)]
+pub struct AbsClient {
+    http: Client,
+    base_url: String,
+    token: String,
+    timeout: Duration,
+}
+
+/// The HTTP client shared by every request. Audiobookshelf requests set
+/// `request_timeout_secs` themselves; the client-wide timeout is a backstop
+/// for anything that doesn't.
+pub fn build_http_client(config: &Config) -> Result<Client, AppError> {
+    let builder = Client::builder()
+        .connect_timeout(Duration::from_secs(
+            config.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS),
+        ))
+        .timeout(request_timeout(config))
+        .pool_idle_timeout(Duration::from_secs(POOL_IDLE_TIMEOUT_SECS))
+        .tcp_keepalive(Duration::from_secs(TCP_KEEPALIVE_SECS));
+    let builder = with_tls_settings(
+        builder,
+        config.tls_ca_file.as_deref(),
+        config.tls_accept_invalid_certs.unwrap_or(false),
+    )?;
+    builder.build().map_err(AppError::AbsRequest)
+}
+
+/// Trusts the certificates in `ca_file` on top of the system ones, or any
+/// certificate at all with `accept_invalid_certs`.
+pub fn with_tls_settings(
+    mut builder: ClientBuilder,
+    ca_file: Option<&str>,
+    accept_invalid_certs: bool,
+) -> Result<ClientBuilder, AppError> {
+    if let Some(ca_file) = ca_file {
+        for certificate in load_ca_file(ca_file)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    if accept_invalid_certs {
+        warn!(
+            "tls_accept_invalid_certs is on: Audiobookshelf's certificate is NOT checked, so anyone who can \
+             intercept the connection can read your API token. Prefer tls_ca_file"
+        );
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+/// Reads every certificate of a PEM file, which may be a bundle.
+pub(crate) fn load_ca_file(path: &str) -> Result<Vec<Certificate>, AppError> {
+    let invalid = |reason: String| AppError::ConfigError(format!("Invalid config: tls_ca_file {}: {}", path, reason));
+    let pem = fs::read(path).map_err(|e| invalid(e.to_string()))?;
+    let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| invalid(e.to_string()))?;
+    if certificates.is_empty() {
+        return Err(invalid("no PEM certificates found".to_string()));
+    }
+    Ok(certificates)
+}
+
+impl AbsClient {
+    pub fn new(http: &Client, base_url: &str, token: &str, timeout: Duration) -> Self {
+        AbsClient {
+            http: http.clone(),
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            timeout,
+        }
+    }
+
+    pub fn for_account(http: &Client, account: &AccountConfig, timeout: Duration) -> Self {
+        AbsClient::new(http, &account.audiobookshelf_url, &account.audiobookshelf_token, timeout)
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// The `limit` most recent listening sessions of the token's user.
+    pub async fn latest_sessions(&self, limit: u32) -> Result<Vec<Session>, AppError> {
+        let url = format!("{}/api/me/listening-sessions?itemsPerPage={}", self.base_url, limit);
+        let resp: ListeningSessionsResponse = self.get_json(&url).await?;
+        Ok(resp.sessions)
+    }
+
+    pub async fn library_item(&self, id: &str) -> Result<LibraryItemResponse, AppError> {
+        let url = format!("{}/api/items/{}?include=chapters", self.base_url, id);
+        self.get_json(&url).await
+    }
+
+    pub async fn media_progress(&self, item_id: &str, episode_id: Option<&str>) -> Result<MediaProgress, AppError> {
+        let url = match episode_id {
+            Some(episode_id) => format!("{}/api/me/progress/{}/{}", self.base_url, item_id, episode_id),
+            None => format!("{}/api/me/progress/{}", self.base_url, item_id),
+        };
+        self.get_json(&url).await
+    }
+
+    pub async fn cover_bytes(&self, id: &str, width: u32) -> Result<Vec<u8>, AppError> {
+        let url = format!("{}/api/items/{}/cover?width={}", self.base_url, id, width);
+        let resp = self.get(&url).await?;
+        let bytes = resp.bytes().await.map_err(|e| AppError::AbsRequest(redact_request_error(e)))?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Whether `url` answers a HEAD request without the token, i.e. whether
+    /// Discord can load it.
+    pub async fn is_publicly_reachable(&self, url: &str) -> bool {
+        let request = self.http.head(url).header(USER_AGENT, USER_AGENT_VALUE).timeout(self.timeout);
+        match request.send().await {
+            Ok(resp) => resp.status().is_success(),
+            Err(e) => {
+                debug!("HEAD {} failed: {}", redact_url(url), redact_request_error(e));
+                false
+            }
+        }
+    }
+
+    pub async fn me(&self) -> Result<User, AppError> {
+        let url = format!("{}/api/me", self.base_url);
+        self.get_json(&url).await
+    }
+
+    pub async fn search_covers(&self, title: &str, author: &str, provider: &str) -> Result<CoverResponse, AppError> {
+        let url = Url::parse_with_params(
+            &format!("{}/api/search/covers", self.base_url),
+            &[("title", title), ("author", author), ("provider", provider)],
+        )
+        .map_err(|e| AppError::ConfigError(format!("Invalid config: audiobookshelf_url: {}", e)))?;
+        self.get_json(url.as_str()).await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        let resp = self.get(url).await?;
+        resp.json().await.map_err(|e| AppError::AbsDecode(redact_request_error(e)))
+    }
+
+    /// GETs from Audiobookshelf, retrying connection failures, timeouts and
+    /// 5xx responses with jittered exponential backoff. Other errors such as
+    /// 401/403/404 are returned immediately.
+    async fn get(&self, url: &str) -> Result<Response, AppError> {
+        let mut retries = 0;
+        loop {
+            let request = self
+                .http
+                .get(url)
+                .bearer_auth(&self.token)
+                .header(USER_AGENT, USER_AGENT_VALUE)
+                .timeout(self.timeout);
+            let error = match request.send().await {
+                Ok(resp) if resp.status().is_server_error() => AppError::AbsHttp { status: resp.status() },
+                Ok(resp) if !resp.status().is_success() => return Err(AppError::AbsHttp { status: resp.status() }),
+                Ok(resp) => return Ok(resp),
+                Err(e) if e.is_connect() || e.is_timeout() => AppError::AbsRequest(redact_request_error(e)),
+                Err(e) => return Err(AppError::AbsRequest(redact_request_error(e))),
+            };
+
+            if retries >= MAX_REQUEST_RETRIES {
+                return Err(error);
+            }
+            let jitter = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() as f64 / 1e9;
+            let delay = retry_delay(retries, jitter);
+            warn!("Request to {} failed ({}), retrying in {:.1}s", redact_url(url), error, delay.as_secs_f64());
+            time::sleep(delay).await;
+            retries += 1;
+        }
+    }
+}
+
+/// Between half and all of `RETRY_BASE_DELAY_SECS << retries`, picked by
+/// `jitter` (0 to 1), so clients that failed together don't retry in step.
+pub(crate) fn retry_delay(retries: u32, jitter: f64) -> Duration {
+    let max = Duration::from_secs(RETRY_BASE_DELAY_SECS << retries);
+    max.mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// The listening session's position lags behind what players sync to media
+/// progress, so prefer the progress whenever it is at least as recent.
+pub(crate) fn corrected_position(session: &Session, progress: &MediaProgress) -> f64 {
+    match (progress.lastUpdate, session.updatedAt) {
+        (Some(progress_updated), Some(session_updated)) if progress_updated < session_updated => session.currentTime,
+        (None, _) => session.currentTime,
+        _ => progress.currentTime,
+    }
+}
+
+pub(crate) fn session_key(session: &Session) -> String {
+    session.id.clone().unwrap_or_else(|| session.libraryItemId.clone())
+}
+
+/// Picks the most recently updated session. On a tie, a session whose
+/// position moved since the last poll wins over one that sat still.
+pub(crate) fn pick_session(sessions: Vec<Session>, previous_positions: &HashMap<String, f64>) -> Option<Session> {
+    sessions.into_iter().max_by_key(|session| {
+        let moved = previous_positions
+            .get(&session_key(session))
+            .is_some_and(|&previous| (session.currentTime - previous).abs() > f64::EPSILON);
+        (session.last_update().unwrap_or(0), moved)
+    })
+}
+
+/// Uses the session's playback rate when the server reports one, otherwise
+/// estimates it from media time progressed versus wall-clock time listened.
+pub(crate) fn playback_speed(session: &Session) -> f64 {
+    if let Some(rate) = session.playbackRate.filter(|rate| *rate > 0.0) {
+        return rate;
+    }
+    if let (Some(start), Some(listened)) = (session.startTime, session.timeListening) {
+        if listened >= MIN_LISTENING_SECS_FOR_SPEED {
+            let speed = (session.currentTime - start) / listened;
+            if (0.5..=4.0).contains(&speed) {
+                return (speed * 20.0).round() / 20.0;
+            }
+        }
+    }
+    1.0
+}
+
+pub(crate) fn session_passes_filters(config: &Config, session: &Session, filtered_sessions: &mut HashSet<String>) -> bool {
+    // Never a candidate, so another session can be shown instead. Not logged
+    // by title, these are private.
+    if is_ignored(config, session) {
+        if filtered_sessions.insert(session_key(session)) {
+            debug!("Ignoring a session from an ignored library or item");
+        }
+        return false;
+    }
+
+    let device = session.deviceInfo.as_ref();
+    let device_values: Vec<&str> = device
+        .map(|device| {
+            [
+                &device.deviceName,
+                &device.clientName,
+                &device.manufacturer,
+                &device.model,
+                &device.osName,
+                &device.browserName,
+            ]
+            .into_iter()
+            .filter_map(|value| value.as_deref())
+            .collect()
+        })
+        .unwrap_or_default();
+    let player_values: Vec<&str> = session.mediaPlayer.as_deref().into_iter().collect();
+
+    let passes = matches_filter(&config.device_filter, &device_values)
+        && matches_filter(&config.player_filter, &player_values);
+
+    if !passes && filtered_sessions.insert(session_key(session)) {
+        info!(
+            "Ignoring session for \"{}\" from device {:?} / player {:?} (doesn't match device_filter/player_filter)",
+            session.displayTitle, device_values, player_values
+        );
+    }
+    passes
+}
+
+pub(crate) fn matches_filter(patterns: &Option<Vec<String>>, values: &[&str]) -> bool {
+    let patterns = match patterns {
+        Some(patterns) if !patterns.is_empty() => patterns,
+        _ => return true,
+    };
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_lowercase();
+        values.iter().any(|value| {
+            let value = value.to_lowercase();
+            if pattern.contains(['*', '?']) {
+                glob_match(&pattern, &value)
+            } else {
+                value.contains(&pattern)
+            }
+        })
+    })
+}
+
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether the session's library, item or podcast episode is listed in
+/// `ignored_library_ids` or `ignored_item_ids`.
+fn is_ignored(config: &Config, session: &Session) -> bool {
+    let listed = |ids: &Option<Vec<String>>, id: Option<&str>| {
+        id.is_some_and(|id| ids.iter().flatten().any(|ignored| ignored == id))
+    };
+    listed(&config.ignored_library_ids, session.libraryId.as_deref())
+        || listed(&config.ignored_item_ids, Some(&session.libraryItemId))
+        || listed(&config.ignored_item_ids, session.episodeId.as_deref())
+}
+
+/// `updated_at` is the session's `last_update` in milliseconds since the
+/// epoch. Sessions without a timestamp are never considered stale.
+pub(crate) fn is_session_stale(updated_at: Option<u64>, now: SystemTime, stale_after: Duration) -> bool {
+    match updated_at {
+        Some(updated_at) => {
+            let updated_at = UNIX_EPOCH + Duration::from_millis(updated_at);
+            now.duration_since(updated_at).is_ok_and(|age| age > stale_after)
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_session_stale_uses_updated_at() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let window = Duration::from_secs(300);
+        let updated = |secs_ago: u64| Some((1_700_000_000 - secs_ago) * 1000);
+
+        assert!(!is_session_stale(updated(10), now, window));
+        assert!(!is_session_stale(updated(300), now, window));
+        assert!(is_session_stale(updated(301), now, window));
+        assert!(!is_session_stale(None, now, window));
+        assert!(!is_session_stale(Some(1_700_000_060_000), now, window));
+    }
+
+    #[test]
+    fn episode_numbers_accept_strings_and_numbers() {
+        let episodes: Vec<PodcastEpisode> = serde_json::from_value(serde_json::json!([
+            { "id": "ep_1", "season": "2", "episode": "14" },
+            { "id": "ep_2", "season": 2, "episode": 14 },
+            { "id": "ep_3", "season": null },
+        ]))
+        .unwrap();
+        for episode in &episodes[..2] {
+            assert_eq!(episode.season.as_deref(), Some("2"));
+            assert_eq!(episode.episode.as_deref(), Some("14"));
+        }
+        assert_eq!((episodes[2].season.as_deref(), episodes[2].episode.as_deref()), (None, None));
+
+        let series: Vec<SeriesInfo> = serde_json::from_value(serde_json::json!([
+            { "name": "The Stormlight Archive", "sequence": 1 },
+            { "name": "Mistborn", "sequence": "1.5" },
+            { "name": "Discworld", "sequence": 2.5 },
+        ]))
+        .unwrap();
+        let sequences: Vec<_> = series.iter().map(|series| series.sequence.as_deref()).collect();
+        assert_eq!(sequences, [Some("1"), Some("1.5"), Some("2.5")]);
+    }
+
+    #[test]
+    fn retry_delay_doubles_with_jitter() {
+        assert_eq!(retry_delay(0, 0.0), Duration::from_millis(500));
+        assert_eq!(retry_delay(0, 1.0), Duration::from_secs(1));
+        assert_eq!(retry_delay(2, 0.5), Duration::from_secs(3));
+        assert_eq!(retry_delay(1, 7.0), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn last_update_falls_back_to_started_at() {
+        let mut session = session("phone", 100.0, 2000);
+        session.startedAt = Some(1000);
+        assert_eq!(session.last_update(), Some(2000));
+        session.updatedAt = None;
+        assert_eq!(session.last_update(), Some(1000));
+    }
+
+    fn session(id: &str, current_time: f64, updated_at: u64) -> Session {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "displayTitle": id,
+            "displayAuthor": "Author",
+            "currentTime": current_time,
+            "duration": 3600.0,
+            "updatedAt": updated_at,
+            "mediaMetadata": {},
+            "libraryItemId": format!("li_{}", id),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn pick_session_prefers_newest_then_moving() {
+        let previous: HashMap<String, f64> =
+            [("phone".to_string(), 100.0), ("desktop".to_string(), 200.0)].into();
+
+        let picked = pick_session(vec![session("phone", 100.0, 2000), session("desktop", 250.0, 1000)], &previous);
+        assert_eq!(picked.unwrap().displayTitle, "phone");
+
+        let picked = pick_session(vec![session("desktop", 250.0, 1000), session("phone", 100.0, 1000)], &previous);
+        assert_eq!(picked.unwrap().displayTitle, "desktop");
+
+        assert!(pick_session(Vec::new(), &previous).is_none());
+    }
+
+    #[test]
+    fn abs_client_keeps_subpath_and_drops_trailing_slash() {
+        let abs = AbsClient::new(&Client::new(), " https://example.com/audiobookshelf/ ", "token", Duration::from_secs(10));
+        assert_eq!(abs.base_url(), "https://example.com/audiobookshelf");
+    }
+
+    #[test]
+    fn load_ca_file_reads_bundles_and_names_the_path() {
+        const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBhTCCASugAwIBAgIUI53Z5cZYR+14GfjXV9PUv8/1qrowCgYIKoZIzj0EAwIw
+FzEVMBMGA1UEAwwMYWJzLmludGVybmFsMCAXDTI2MTAxODAzMjQ1MVoYDzIxMjYw
+OTI0MDMyNDUxWjAXMRUwEwYDVQQDDAxhYnMuaW50ZXJuYWwwWTATBgcqhkjOPQIB
+BggqhkjOPQMBBwNCAATYAcremw8wybOX7IW+OvMSRhKBSKKf1k22WeckM7Ff9Iwa
+eBs2v3CxKWffM5rFBgiMlMgmJCQHgl6q0hS+UL26o1MwUTAdBgNVHQ4EFgQUe01N
+mDlnNoJDND6T6O2C3m7n368wHwYDVR0jBBgwFoAUe01NmDlnNoJDND6T6O2C3m7n
+368wDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNIADBFAiEA0cvsnNStQL8F
+Uyy5/pxDCBw9AJWkIUEG5m7tJqb56lECIDg1O96geW072SHsDdGKFOfDVcPq08S2
+ivXmy2oHX5s5
+-----END CERTIFICATE-----
+";
+        let path = std::env::temp_dir().join(format!("abs-rpc-ca-{}.pem", std::process::id()));
+        let path_str = path.to_str().unwrap();
+
+        fs::write(&path, format!("{}{}", CERTIFICATE, CERTIFICATE)).unwrap();
+        assert_eq!(load_ca_file(path_str).unwrap().len(), 2);
+
+        fs::write(&path, "not a certificate").unwrap();
+        let error = load_ca_file(path_str).unwrap_err().to_string();
+        assert!(error.contains(path_str) && error.contains("no PEM certificates"), "{}", error);
+
+        fs::remove_file(&path).unwrap();
+        assert!(load_ca_file(path_str).unwrap_err().to_string().contains(path_str));
+    }
+
+    #[test]
+    fn corrected_position_prefers_newer_media_progress() {
+        let progress = |current_time: f64, last_update: Option<u64>| MediaProgress {
+            currentTime: current_time,
+            lastUpdate: last_update,
+        };
+        let session = session("phone", 100.0, 2000);
+
+        assert_eq!(corrected_position(&session, &progress(130.0, Some(5000))), 130.0);
+        assert_eq!(corrected_position(&session, &progress(130.0, Some(2000))), 130.0);
+        assert_eq!(corrected_position(&session, &progress(90.0, Some(1000))), 100.0);
+        assert_eq!(corrected_position(&session, &progress(130.0, None)), 100.0);
+    }
+
+    #[test]
+    fn matches_filter_supports_substrings_and_globs() {
+        let filter = |patterns: &[&str]| Some(patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>());
+
+        assert!(matches_filter(&None, &["Pixel 7"]));
+        assert!(matches_filter(&filter(&["pixel"]), &["Pixel 7"]));
+        assert!(matches_filter(&filter(&["desk*-pc"]), &["Firefox", "Desktop-PC"]));
+        assert!(matches_filter(&filter(&["html?"]), &["html5"]));
+        assert!(!matches_filter(&filter(&["tablet"]), &["Pixel 7"]));
+        assert!(!matches_filter(&filter(&["pixel"]), &[]));
+    }
+}
@@ -0,0 +1,209 @@
+//! The urls.json cover cache.
+
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::AppError;
+
+/// Format written to urls.json. Version 1 (no `version` key) stored bare
+/// URLs in `covers`.
+pub(crate) const COVER_CACHE_VERSION: u32 = 2;
+
+/// Cover lookups persisted to urls.json next to the config file. Titles with
+/// no cover are remembered in `misses` (unix seconds) so the providers are
+/// only searched again once the miss is older than a day.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CoverCache {
+    #[serde(skip)]
+    pub(crate) path: PathBuf,
+    #[serde(default = "legacy_version")]
+    pub(crate) version: u32,
+    #[serde(default, deserialize_with = "deserialize_covers")]
+    pub(crate) covers: HashMap<String, CachedCover>,
+    #[serde(default)]
+    pub(crate) misses: HashMap<String, u64>,
+}
+
+/// A found cover, where it came from and when (unix seconds; 0 for entries
+/// migrated from version 1).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedCover {
+    pub url: String,
+    pub source: CoverSource,
+    pub cached_at: u64,
+    /// Library item the cover was looked up for, so `clear-cache --item`
+    /// finds search results too. Unknown for migrated entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub item_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSource {
+    /// Audiobookshelf's cover search.
+    Search,
+    /// The item's own cover on the Audiobookshelf server.
+    Abs,
+}
+
+impl Default for CoverCache {
+    fn default() -> Self {
+        CoverCache {
+            path: PathBuf::new(),
+            version: COVER_CACHE_VERSION,
+            covers: HashMap::new(),
+            misses: HashMap::new(),
+        }
+    }
+}
+
+impl CoverCache {
+    pub(crate) fn cover_url(&self, key: &str) -> Option<&str> {
+        self.covers.get(key).map(|cover| cover.url.as_str())
+    }
+
+    pub(crate) fn insert_cover(&mut self, key: String, url: String, source: CoverSource, item_id: &str) {
+        let cached_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.misses.remove(&key);
+        self.covers.insert(
+            key,
+            CachedCover {
+                url,
+                source,
+                cached_at,
+                item_id: Some(item_id.to_string()),
+            },
+        );
+    }
+
+    /// Forgets everything cached for a library item ID or an "Author - Title"
+    /// key, returning how many entries were dropped.
+    pub fn remove_item(&mut self, item: &str) -> usize {
+        let abs_cover = format!("/api/items/{}/cover", item);
+        let matches = |key: &str| key == item || key.ends_with(&abs_cover);
+        let before = self.covers.len() + self.misses.len();
+        self.covers.retain(|key, cover| !matches(key) && cover.item_id.as_deref() != Some(item));
+        self.misses.retain(|key, _| !matches(key));
+        before - self.covers.len() - self.misses.len()
+    }
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Reads `covers` in either format. Version 1 only knew the URL; entries
+/// keyed by their own URL were Audiobookshelf covers.
+fn deserialize_covers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, CachedCover>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StoredCover {
+        Entry(CachedCover),
+        Url(String),
+    }
+
+    let stored = HashMap::<String, StoredCover>::deserialize(deserializer)?;
+    Ok(stored
+        .into_iter()
+        .map(|(key, cover)| {
+            let cover = match cover {
+                StoredCover::Entry(cover) => cover,
+                StoredCover::Url(url) => CachedCover {
+                    source: if url == key { CoverSource::Abs } else { CoverSource::Search },
+                    url,
+                    cached_at: 0,
+                    item_id: None,
+                },
+            };
+            (key, cover)
+        })
+        .collect())
+}
+
+pub fn load_cover_cache(path: &Path) -> CoverCache {
+    let mut cache = match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Ignoring unreadable cover cache {}: {}", path.display(), e);
+            CoverCache::default()
+        }),
+        Err(_) => CoverCache::default(),
+    };
+    if cache.version > COVER_CACHE_VERSION {
+        warn!(
+            "Cover cache {} was written by a newer version (format {}), it will be rewritten as format {}",
+            path.display(),
+            cache.version,
+            COVER_CACHE_VERSION
+        );
+    }
+    cache.version = COVER_CACHE_VERSION;
+    cache.path = path.to_path_buf();
+    cache
+}
+
+pub fn save_cover_cache(cache: &CoverCache) -> Result<(), AppError> {
+    if let Some(parent) = cache.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(AppError::CacheError)?;
+    }
+    let contents = serde_json::to_string_pretty(cache).map_err(|e| AppError::CacheError(e.into()))?;
+    fs::write(&cache.path, contents).map_err(AppError::CacheError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_1_caches_are_migrated() {
+        let abs_url = "https://abs.example.com/api/items/li_1/cover";
+        let legacy = serde_json::json!({
+            "covers": { "Brandon Sanderson - The Way of Kings": "https://covers.example/1.jpg", abs_url: abs_url },
+            "misses": { "Someone - Unknown": 1_700_000_000 }
+        });
+
+        let cache: CoverCache = serde_json::from_value(legacy).unwrap();
+        assert_eq!(cache.version, 1);
+        assert_eq!(
+            cache.covers["Brandon Sanderson - The Way of Kings"],
+            CachedCover {
+                url: "https://covers.example/1.jpg".to_string(),
+                source: CoverSource::Search,
+                cached_at: 0,
+                item_id: None
+            }
+        );
+        assert_eq!(cache.covers[abs_url].source, CoverSource::Abs);
+        assert_eq!(cache.misses["Someone - Unknown"], 1_700_000_000);
+
+        let saved = serde_json::to_value(CoverCache { version: COVER_CACHE_VERSION, ..cache }).unwrap();
+        assert_eq!(saved["version"], 2);
+        assert_eq!(saved["covers"][abs_url]["source"], "abs");
+        let reloaded: CoverCache = serde_json::from_value(saved).unwrap();
+        assert_eq!(reloaded.cover_url(abs_url), Some(abs_url));
+    }
+
+    #[test]
+    fn remove_item_matches_ids_and_keys() {
+        let mut cache = CoverCache::default();
+        let mut insert = |key: &str, url: &str, source, item_id| {
+            cache.insert_cover(key.to_string(), url.to_string(), source, item_id)
+        };
+        let abs_url = "https://abs.example.com/api/items/li_1/cover";
+        insert("Brandon Sanderson - The Way of Kings", "https://covers.example/1.jpg", CoverSource::Search, "li_1");
+        insert(abs_url, abs_url, CoverSource::Abs, "li_1");
+        insert("Someone - Else", "https://covers.example/2.jpg", CoverSource::Search, "li_2");
+        cache.misses.insert("https://abs.example.com/api/items/li_3/cover".to_string(), 1_700_000_000);
+        cache.misses.insert("Someone - Unknown".to_string(), 1_700_000_000);
+
+        assert_eq!(cache.remove_item("li_1"), 2);
+        assert_eq!(cache.remove_item("li_1"), 0);
+        assert_eq!(cache.remove_item("li_3"), 1);
+        assert_eq!(cache.remove_item("Someone - Unknown"), 1);
+        assert_eq!(cache.remove_item("Someone - Else"), 1);
+        assert!(cache.covers.is_empty() && cache.misses.is_empty());
+    }
+}
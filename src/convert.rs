@@ -0,0 +1,146 @@
+//! `--convert-config`: rewrites a config.json as a commented config.toml.
+
+use serde_json::{Map, Value};
+
+/// One-line description written above each key in the generated TOML.
+pub(crate) const FIELD_DOCS: &[(&str, &str)] = &[
+    ("discord_client_id", "Application ID of the Discord app the presence is shown for"),
+    ("audiobookshelf_url", "Audiobookshelf server, including the port or subpath"),
+    ("audiobookshelf_token", "API token from your user in the Audiobookshelf settings"),
+    ("audiobookshelf_token_file", "File holding the token instead, e.g. a systemd credential"),
+    ("accounts", "More Audiobookshelf accounts to watch, the first one playing is shown"),
+    ("show_chapters", "Cover hover text: \"chapter\", \"genre\", \"both\" or \"none\" (true/false also work)"),
+    ("show_chapter_numbers", "Prefix chapter titles with their number"),
+    ("chapter_prefixes", "More words that start an already numbered chapter title, like \"Part\""),
+    ("max_genres", "How many genres are shown, joined with \" / \""),
+    ("genre_blocklist", "Genres that are never shown"),
+    ("cover_providers", "Cover search providers, in order of preference"),
+    ("use_abs_cover", "Use the Audiobookshelf cover when the server is publicly reachable"),
+    ("timestamp_mode", "What the progress bar spans: \"book\" or \"chapter\""),
+    ("seek_threshold_seconds", "Jumps larger than this count as a seek"),
+    ("pause_after_polls", "Polls without progress before playback counts as paused"),
+    ("playback_speed", "Override the playback speed used for the remaining time"),
+    ("time_display", "Which times Discord shows: \"elapsed\", \"remaining\" or \"both\""),
+    ("poll_interval_seconds", "Seconds between polls while something is playing"),
+    ("poll_interval_secs", "Seconds between polls while something is playing"),
+    ("idle_poll_interval_seconds", "Seconds between polls once nothing has played for a while"),
+    ("idle_polls_before_backoff", "Idle polls before switching to idle_poll_interval_seconds"),
+    ("details_template", "First line of the presence, e.g. \"{title}\""),
+    ("details_format", "First line of the presence, e.g. \"{title}\""),
+    ("state_template", "Second line of the presence, e.g. \"{author}\""),
+    ("state_format", "Second line of the presence, e.g. \"{author}\""),
+    ("large_text_template", "Text shown when hovering over the cover"),
+    ("show_progress", "Add the progress percentage"),
+    ("show_time_remaining", "Add the time left, e.g. \"4h 12m left\", to the hover text"),
+    ("show_chapter_time_left", "Add the time left in the chapter, e.g. \"Chapter 5 (3:21 left)\""),
+    ("show_series", "Add the series name and number after the author"),
+    ("show_author", "Show the author on the second line"),
+    ("show_narrator", "Show the narrator"),
+    ("narrator_display", "\"append\" the narrator to the author or \"replace\" the author"),
+    ("state_content", "\"author\", \"narrator\" or \"author_and_narrator\", instead of the three settings above"),
+    ("activity_type", "\"listening\", \"playing\", \"watching\" or \"competing\""),
+    ("buttons", "Buttons under the presence, at most two"),
+    ("quiet_hours", "Local time window (\"HH:MM\") during which nothing is shown"),
+    ("schedule", "Local time windows per weekday during which the presence is held back"),
+    ("suppress_when_running", "Hide the presence while any of these programs is running"),
+    ("show_when_paused", "Keep showing the book while it is paused"),
+    ("clear_on_pause", "Clear the presence when playback is paused"),
+    ("paused_timeout_secs", "Clear a paused book after this many seconds"),
+    ("stale_session_secs", "Ignore sessions the server hasn't updated, or clear after failing, for this long"),
+    ("min_play_seconds", "Only show a session once this many seconds of it were listened to"),
+    ("library_item_ttl_secs", "Fetch a book's chapters again after this many seconds"),
+    ("use_media_progress", "Also use the saved media progress when it is newer"),
+    ("request_timeout_secs", "Give up on an Audiobookshelf request after this many seconds"),
+    ("connect_timeout_secs", "Give up connecting to a server after this many seconds"),
+    ("tls_ca_file", "PEM file with the CA certificate(s) of a server with a private certificate"),
+    ("tls_accept_invalid_certs", "Don't check the server's certificate at all (insecure)"),
+    ("device_filter", "Only show sessions from devices matching one of these"),
+    ("player_filter", "Only show sessions from players matching one of these"),
+    ("small_image_key", "Discord app asset shown in the corner of the cover"),
+    ("small_image_text", "Text shown when hovering over the small image"),
+    ("blocklist", "Titles, authors, series or genres never shown (\"patterns\", words or */? globs, no regexes), cleared or as a placeholder (\"action\")"),
+    ("privacy_mode", "Only ever show \"Listening to an audiobook\""),
+    ("ignored_library_ids", "Audiobookshelf library IDs that are never shown"),
+    ("ignored_item_ids", "Book, podcast or episode IDs that are never shown"),
+    ("books", "Display settings for books only, e.g. show_chapters"),
+    ("podcasts", "Display settings for podcasts only, \"enabled\": false hides them"),
+    ("use_websocket", "Update immediately on Audiobookshelf socket events"),
+    ("check_for_updates", "Check GitHub for a new release at startup"),
+    ("discord_wait_timeout_secs", "Give up if Discord isn't running within this many seconds"),
+    ("discord_ipc_path", "Discord IPC socket or its directory, for Flatpak/Snap setups"),
+    ("library_client_ids", "Discord application ID per Audiobookshelf library ID"),
+    ("status_port", "Serve /health and /status on this port"),
+    ("status_bind", "Address the status server listens on"),
+    ("cache_path", "Where the urls.json cover cache is kept, instead of next to the config"),
+    ("log_format", "\"text\" or \"json\" log lines"),
+];
+
+/// Writes `config` as TOML with each known key preceded by its description.
+/// Keys are kept in the documented order, then unknown ones; tables go last
+/// as TOML requires. Unset (`null`) values are left out.
+pub fn config_to_toml(config: &Map<String, Value>) -> Result<String, toml::ser::Error> {
+    let position = |key: &str| FIELD_DOCS.iter().position(|(field, _)| *field == key).unwrap_or(FIELD_DOCS.len());
+    let mut keys: Vec<&String> = config.keys().filter(|key| !config[key.as_str()].is_null()).collect();
+    keys.sort_by_key(|key| (is_table(&config[key.as_str()]), position(key)));
+
+    let mut toml = String::new();
+    for key in keys {
+        if let Some((_, doc)) = FIELD_DOCS.iter().find(|(field, _)| field == key) {
+            toml.push_str(&format!("# {}\n", doc));
+        }
+        let mut entry = Map::new();
+        entry.insert(key.clone(), without_nulls(&config[key.as_str()]));
+        toml.push_str(&toml::to_string_pretty(&entry)?);
+        toml.push('\n');
+    }
+    Ok(toml)
+}
+
+fn is_table(value: &Value) -> bool {
+    match value {
+        Value::Object(_) => true,
+        Value::Array(items) => items.first().is_some_and(Value::is_object),
+        _ => false,
+    }
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(without_nulls).collect()),
+        value => value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChapterDisplay, Config};
+
+    #[test]
+    fn converted_config_reads_back_the_same() {
+        let json = serde_json::json!({
+            "buttons": { "public_url": null, "links": [{ "label": "Open", "url": "{abs_url}/item/{libraryItemId}" }] },
+            "discord_client_id": "1283070638088650752",
+            "audiobookshelf_url": "http://localhost:13378",
+            "audiobookshelf_token": "token",
+            "show_chapters": true,
+            "playback_speed": null,
+            "cover_providers": ["audible", "google"]
+        });
+
+        let toml = config_to_toml(json.as_object().unwrap()).unwrap();
+        assert!(toml.starts_with("# Application ID of the Discord app"), "{}", toml);
+        assert!(!toml.contains("playback_speed"), "{}", toml);
+
+        let config: Config = toml::from_str(&toml).unwrap();
+        assert_eq!(config.audiobookshelf_token.as_deref(), Some("token"));
+        assert_eq!(config.show_chapters, Some(ChapterDisplay::Chapter));
+        assert_eq!(config.buttons.unwrap().links.unwrap()[0].url, "{abs_url}/item/{libraryItemId}");
+    }
+}
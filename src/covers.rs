@@ -0,0 +1,125 @@
+//! Cover art lookup through Audiobookshelf's cover search, or the item's
+//! own cover when Discord can reach the server.
+
+use futures::future::join_all;
+use log::{info, warn};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::abs_client::AbsClient;
+use crate::cache::{save_cover_cache, CoverCache, CoverSource};
+use crate::error::{redact_url, AppError};
+use crate::format::{extract_book_number, get_base_title};
+use crate::presence::is_private_url;
+
+pub(crate) const DEFAULT_COVER_PROVIDERS: &[&str] = &[
+    "audible",
+    "google",
+    "audible.jp",
+    "openlibrary",
+    "itunes",
+    "audible.ca",
+    "audible.uk",
+    "audible.au",
+    "audible.fr",
+    "audible.de",
+    "audible.it",
+    "audible.in",
+    "audible.es",
+    "fantlab",
+];
+pub(crate) const COVER_MISS_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// The item's cover on the Audiobookshelf server at `base_url`, if Discord
+/// can load it. Private addresses are skipped without a request; the HEAD
+/// check's result is cached like a cover search.
+pub(crate) async fn get_abs_cover_url(
+    abs: &AbsClient,
+    cover_cache: &mut CoverCache,
+    base_url: &str,
+    item_id: &str,
+) -> Option<String> {
+    if is_private_url(base_url) {
+        return None;
+    }
+    let url = format!("{}/api/items/{}/cover", base_url, item_id);
+    if cover_cache.covers.contains_key(&url) {
+        return Some(url);
+    }
+    let now_secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if let Some(&missed_at) = cover_cache.misses.get(&url) {
+        if now_secs.saturating_sub(missed_at) < COVER_MISS_TTL_SECS {
+            return None;
+        }
+    }
+
+    let reachable = abs.is_publicly_reachable(&url).await;
+    if reachable {
+        cover_cache.insert_cover(url.clone(), url.clone(), CoverSource::Abs, item_id);
+    } else {
+        info!("{} isn't publicly reachable, searching the cover providers instead", redact_url(&url));
+        cover_cache.misses.insert(url.clone(), now_secs);
+    }
+    if let Err(e) = save_cover_cache(cover_cache) {
+        warn!("Failed to save cover cache {}: {}", cover_cache.path.display(), e);
+    }
+    reachable.then_some(url)
+}
+
+pub async fn get_cover_path(
+    abs: &AbsClient,
+    cover_cache: &mut CoverCache,
+    cover_providers: &Option<Vec<String>>,
+    item_id: &str,
+    title: &str,
+    author: &str,
+) -> Result<Option<String>, AppError> {
+    let cache_key = format!("{} - {}", author, title);
+    if let Some(url) = cover_cache.cover_url(&cache_key) {
+        return Ok(Some(url.to_string()));
+    }
+    let now_secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if let Some(&missed_at) = cover_cache.misses.get(&cache_key) {
+        if now_secs.saturating_sub(missed_at) < COVER_MISS_TTL_SECS {
+            return Ok(None);
+        }
+    }
+
+    let search_title = if let Some(book_num) = extract_book_number(title) {
+        format!("{} {}", get_base_title(title), book_num)
+    } else {
+        get_base_title(title).to_string()
+    };
+
+    let providers: Vec<&str> = match cover_providers {
+        Some(providers) => providers.iter().map(String::as_str).collect(),
+        None => DEFAULT_COVER_PROVIDERS.to_vec(),
+    };
+
+    let futures = providers.iter().map(|provider| {
+        let title = search_title.as_str();
+        async move {
+            let resp = abs.search_covers(title, author, provider).await?;
+            Ok(resp.results.first().cloned())
+        }
+    });
+
+    let results: Vec<Result<Option<String>, AppError>> = join_all(futures).await;
+    let any_failed = results.iter().any(Result::is_err);
+    let cover_url = results.into_iter().find_map(|result| result.ok().flatten());
+
+    match &cover_url {
+        Some(url) => {
+            cover_cache.insert_cover(cache_key, url.clone(), CoverSource::Search, item_id);
+        }
+        None if !any_failed => {
+            info!("No cover found for {}, not searching again for a day", cache_key);
+            cover_cache.misses.insert(cache_key, now_secs);
+        }
+        None => return Ok(None),
+    }
+    if let Err(e) = save_cover_cache(cover_cache) {
+        warn!("Failed to save cover cache {}: {}", cover_cache.path.display(), e);
+    }
+
+    Ok(cover_url)
+}
@@ -0,0 +1,335 @@
+//! Connecting to Discord's IPC socket.
+
+use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+use log::{debug, info, warn};
+use std::env;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::time;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// Delays between Discord reconnect attempts; the last one repeats.
+pub(crate) const DISCORD_RECONNECT_DELAYS_SECS: &[u64] = &[5, 10, 30, 60];
+/// Where Flatpak/Snap Discord builds put their IPC socket, relative to the
+/// usual runtime/temp directories.
+pub(crate) const DISCORD_IPC_SUBPATHS: &[&str] = &[
+    "",
+    "app/com.discordapp.Discord",
+    "app/dev.vencord.Vesktop",
+    ".flatpak/com.discordapp.Discord/xdg-run",
+    "snap.discord",
+    "snap.discord-canary",
+];
+/// Only every Nth failed reconnect is logged at warn level.
+pub(crate) const DISCORD_RECONNECT_LOG_EVERY: u32 = 10;
+
+/// Where activity updates are sent: Discord itself, stdout for `--dry-run`,
+/// or a recording sink in tests.
+pub trait PresenceSink {
+    fn set(&mut self, activity: &ActivityUpdate) -> Result<(), AppError>;
+    fn clear(&mut self) -> Result<(), AppError>;
+    /// Connects if not connected already. Returns how long to wait before
+    /// the next attempt, or `None` once connected.
+    fn reconnect(&mut self) -> Option<Duration>;
+    /// Switches to another Discord application for the following updates.
+    fn use_client_id(&mut self, _client_id: &str) {}
+}
+
+/// The Discord IPC connection. A failed `set` or `clear` drops the client, so
+/// the next `reconnect` starts over with backoff.
+pub struct DiscordSink {
+    client_id: String,
+    client: Option<DiscordIpcClient>,
+    /// The socket last reported as connected to, so it's logged once per change.
+    socket: Option<PathBuf>,
+    failures: u32,
+}
+
+impl DiscordSink {
+    pub fn new(config: &Config) -> Self {
+        DiscordSink {
+            client_id: config.discord_client_id.clone(),
+            client: None,
+            socket: None,
+            failures: 0,
+        }
+    }
+
+    fn connected(&mut self) -> Result<&mut DiscordIpcClient, AppError> {
+        self.client
+            .as_mut()
+            .ok_or_else(|| AppError::DiscordIo("not connected".to_string()))
+    }
+
+    fn disconnect_on_error<T>(&mut self, result: Result<T, AppError>) -> Result<T, AppError> {
+        if result.as_ref().is_err_and(AppError::is_reconnectable) {
+            self.client = None;
+        }
+        result
+    }
+}
+
+impl PresenceSink for DiscordSink {
+    fn set(&mut self, update: &ActivityUpdate) -> Result<(), AppError> {
+        let mut activity_builder = activity::Activity::new()
+            .details(&update.details)
+            .activity_type(update.activity_type.activity_type());
+        if !update.state.is_empty() {
+            activity_builder = activity_builder.state(&update.state);
+        }
+
+        if update.start.is_some() || update.end.is_some() {
+            let mut timestamps = activity::Timestamps::new();
+            if let Some(start) = update.start {
+                timestamps = timestamps.start(start);
+            }
+            if let Some(end) = update.end {
+                timestamps = timestamps.end(end);
+            }
+            activity_builder = activity_builder.timestamps(timestamps);
+        }
+
+        if !update.buttons.is_empty() {
+            activity_builder = activity_builder.buttons(
+                update
+                    .buttons
+                    .iter()
+                    .map(|(label, url)| activity::Button::new(label, url))
+                    .collect(),
+            );
+        }
+
+        if let Some(ref url) = update.large_image {
+            let mut assets = activity::Assets::new().large_image(url);
+            if !update.large_text.is_empty() {
+                assets = assets.large_text(&update.large_text);
+            }
+            if let Some(ref small_image_key) = update.small_image {
+                assets = assets.small_image(small_image_key).small_text(&update.small_text);
+            }
+            activity_builder = activity_builder.assets(assets);
+        }
+
+        let result = self
+            .connected()
+            .and_then(|client| client.set_activity(activity_builder).map_err(AppError::discord));
+        self.disconnect_on_error(result)
+    }
+
+    fn clear(&mut self) -> Result<(), AppError> {
+        let result = self
+            .connected()
+            .and_then(|client| client.clear_activity().map_err(AppError::discord));
+        self.disconnect_on_error(result)
+    }
+
+    /// Drops the connection so the next `reconnect` registers as the new
+    /// application, then tries once right away.
+    fn use_client_id(&mut self, client_id: &str) {
+        if client_id == self.client_id {
+            return;
+        }
+        info!("Switching to Discord application {}", client_id);
+        self.client_id = client_id.to_string();
+        self.client = None;
+        self.failures = 0;
+        self.reconnect();
+    }
+
+    /// Each attempt uses a fresh client (dropping the old socket) so a
+    /// restarted Discord listening on a different IPC pipe is picked up.
+    fn reconnect(&mut self) -> Option<Duration> {
+        if self.client.is_some() {
+            return None;
+        }
+        let result = DiscordIpcClient::new(&self.client_id).and_then(|mut client| {
+            client.connect()?;
+            Ok(client)
+        });
+        match result {
+            Ok(client) => {
+                self.client = Some(client);
+                let socket = find_discord_ipc_socket(None).filter(|socket| self.socket.as_ref() != Some(socket));
+                if let Some(socket) = socket {
+                    info!("Using Discord IPC socket {}", socket.display());
+                    self.socket = Some(socket);
+                }
+                if self.failures > 0 {
+                    info!("Reconnected to Discord after {} failed attempts", self.failures);
+                } else {
+                    info!("Audiobookshelf Discord RPC Connected!");
+                }
+                self.failures = 0;
+                None
+            }
+            Err(e) => {
+                let delay_index = (self.failures as usize).min(DISCORD_RECONNECT_DELAYS_SECS.len() - 1);
+                let retry_in = Duration::from_secs(DISCORD_RECONNECT_DELAYS_SECS[delay_index]);
+                self.failures += 1;
+                if self.failures == 1 || self.failures.is_multiple_of(DISCORD_RECONNECT_LOG_EVERY) {
+                    warn!(
+                        "Couldn't connect to Discord ({}, attempt {}), retrying in {} seconds",
+                        e, self.failures, retry_in.as_secs()
+                    );
+                } else {
+                    debug!("Couldn't connect to Discord ({}), retrying in {} seconds", e, retry_in.as_secs());
+                }
+                Some(retry_in)
+            }
+        }
+    }
+}
+
+/// Prints the activity instead of sending it, for `--dry-run`.
+pub struct DryRunSink;
+
+impl PresenceSink for DryRunSink {
+    fn set(&mut self, update: &ActivityUpdate) -> Result<(), AppError> {
+        println!("[dry-run] Details:    {}", update.details);
+        println!("[dry-run] State:      {}", update.state);
+        println!("[dry-run] Large text: {}", update.large_text);
+        println!("[dry-run] Chapter:    {}", update.chapter.as_deref().unwrap_or("-"));
+        println!("[dry-run] Cover:      {}", update.large_image.as_deref().unwrap_or("-"));
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), AppError> {
+        println!("[dry-run] Activity cleared");
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Option<Duration> {
+        None
+    }
+}
+
+/// The directories the Discord client looks in for `discord-ipc-N`: the
+/// first of XDG_RUNTIME_DIR, TMPDIR, TMP and TEMP that is set, then /tmp.
+#[cfg(unix)]
+fn default_ipc_dirs() -> Vec<PathBuf> {
+    ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .filter_map(env::var_os)
+        .map(PathBuf::from)
+        .chain([PathBuf::from("/tmp")])
+        .collect()
+}
+
+/// Finds the `discord-ipc-N` socket Discord listens on, in the configured
+/// directory (or the directory of a configured socket) or else in the usual
+/// runtime/temp directories and their Flatpak/Snap subdirectories.
+#[cfg(unix)]
+pub fn find_discord_ipc_socket(configured: Option<&str>) -> Option<PathBuf> {
+    let dirs = match configured {
+        Some(path) if Path::new(path).is_dir() => vec![PathBuf::from(path)],
+        Some(path) => vec![Path::new(path).parent()?.to_path_buf()],
+        None => default_ipc_dirs()
+            .iter()
+            .flat_map(|base| DISCORD_IPC_SUBPATHS.iter().map(move |subpath| base.join(subpath)))
+            .collect(),
+    };
+    dirs.iter()
+        .flat_map(|dir| (0..10).map(move |i| dir.join(format!("discord-ipc-{}", i))))
+        .find(|socket| socket.exists())
+}
+
+#[cfg(not(unix))]
+pub fn find_discord_ipc_socket(_configured: Option<&str>) -> Option<PathBuf> {
+    None
+}
+
+/// Points the Discord client at the directory holding the IPC socket when it
+/// isn't the default one. The pinned discord-rich-presence only looks for
+/// `discord-ipc-N` under the first of XDG_RUNTIME_DIR, TMPDIR, TMP and TEMP,
+/// so Flatpak, Snap and bind-mounted sockets are reached by overriding
+/// XDG_RUNTIME_DIR. Call it once, before any other thread is started:
+/// changing the environment while other threads run is a data race.
+#[cfg(unix)]
+pub fn use_discord_ipc_dir(configured: Option<&str>) {
+    let Some(dir) = find_discord_ipc_socket(configured).and_then(|socket| socket.parent().map(Path::to_path_buf))
+    else {
+        return;
+    };
+    if default_ipc_dirs().first() != Some(&dir) {
+        env::set_var("XDG_RUNTIME_DIR", &dir);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn use_discord_ipc_dir(_configured: Option<&str>) {}
+
+/// Retries `reconnect` until Discord is up, so the service can start before
+/// Discord does.
+pub async fn wait_for_discord(
+    sink: &mut dyn PresenceSink,
+    timeout: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let started = SystemTime::now();
+    let mut attempts = 0;
+    while let Some(retry_in) = sink.reconnect() {
+        attempts += 1;
+        if attempts == 1 {
+            info!("Waiting for Discord to start...");
+        }
+        if let Some(timeout) = timeout {
+            if started.elapsed().unwrap_or_default() + retry_in > timeout {
+                return Err(format!("Discord didn't start within {} seconds", timeout.as_secs()).into());
+            }
+        }
+        time::sleep(retry_in).await;
+    }
+    Ok(())
+}
+
+/// The verb Discord shows before the activity name, set with `activity_type`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PresenceType {
+    #[default]
+    Listening,
+    Playing,
+    Watching,
+    Competing,
+}
+
+impl PresenceType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "listening" => Some(PresenceType::Listening),
+            "playing" => Some(PresenceType::Playing),
+            "watching" => Some(PresenceType::Watching),
+            "competing" => Some(PresenceType::Competing),
+            _ => None,
+        }
+    }
+
+    fn activity_type(self) -> activity::ActivityType {
+        match self {
+            PresenceType::Listening => activity::ActivityType::Listening,
+            PresenceType::Playing => activity::ActivityType::Playing,
+            PresenceType::Watching => activity::ActivityType::Watching,
+            PresenceType::Competing => activity::ActivityType::Competing,
+        }
+    }
+}
+
+/// What gets shown for the current session, before it is turned into a
+/// Discord activity. Timestamps are unix seconds; `small_image` is only shown
+/// together with a `large_image`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActivityUpdate {
+    pub details: String,
+    pub state: String,
+    pub large_text: String,
+    pub large_image: Option<String>,
+    pub small_image: Option<String>,
+    pub small_text: String,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+    pub buttons: Vec<(String, String)>,
+    pub chapter: Option<String>,
+    pub activity_type: PresenceType,
+}
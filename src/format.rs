@@ -0,0 +1,588 @@
+//! Text shown in the presence: templates, chapter and series names.
+
+use crate::abs_client::{Chapter, SeriesInfo};
+
+pub(crate) const DISCORD_FIELD_MAX_LEN: usize = 128;
+pub(crate) const CHAPTER_WORDS: &[&str] = &[
+    "chapter",
+    "chap.",
+    "ch.",
+    "kapitel",
+    "hoofdstuk",
+    "capítulo",
+    "capitulo",
+    "chapitre",
+    "capitolo",
+];
+
+/// Values available to the details/state/large_text templates.
+#[derive(Debug, Default)]
+pub(crate) struct TemplateContext {
+    pub(crate) title: String,
+    pub(crate) author: String,
+    pub(crate) narrator: String,
+    pub(crate) series: String,
+    pub(crate) sequence: String,
+    pub(crate) chapter: String,
+    pub(crate) chapter_number: String,
+    pub(crate) chapter_count: String,
+    pub(crate) genre: String,
+    pub(crate) progress_percent: u32,
+    pub(crate) current_time: f64,
+    pub(crate) duration: f64,
+    /// Listening time left at the current speed, see `format_duration`.
+    pub(crate) time_left: String,
+    pub(crate) podcast_title: String,
+    pub(crate) episode: String,
+    pub(crate) season: String,
+    pub(crate) library_item_id: String,
+    /// Audiobookshelf URL for links, without a trailing slash.
+    pub(crate) abs_url: String,
+}
+
+pub(crate) fn template_placeholders(template: &str) -> Vec<&str> {
+    let mut placeholders = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        match after.find(['{', '}']) {
+            Some(end) if after[end..].starts_with('}') => {
+                placeholders.push(&after[..end]);
+                rest = &after[end + 1..];
+            }
+            Some(end) => rest = &after[end..],
+            None => break,
+        }
+    }
+    placeholders
+}
+
+pub(crate) fn template_value(context: &TemplateContext, name: &str) -> Option<String> {
+    let value = match name {
+        "title" => context.title.clone(),
+        "author" => context.author.clone(),
+        "narrator" => context.narrator.clone(),
+        "series" => context.series.clone(),
+        "sequence" => context.sequence.clone(),
+        "chapter" => context.chapter.clone(),
+        "chapter_number" => context.chapter_number.clone(),
+        "chapter_count" => context.chapter_count.clone(),
+        "genre" => context.genre.clone(),
+        "progress" | "progress_percent" => context.progress_percent.to_string(),
+        "current_time" => format_timestamp(context.current_time),
+        "duration" => format_timestamp(context.duration),
+        "time_left" => context.time_left.clone(),
+        "podcast_title" => context.podcast_title.clone(),
+        "episode" => context.episode.clone(),
+        "season" => context.season.clone(),
+        "libraryItemId" => context.library_item_id.clone(),
+        "abs_url" => context.abs_url.clone(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Replaces `{placeholder}`s with values from the context. Unknown
+/// placeholders render as empty; a `{` without a closing brace is kept.
+pub(crate) fn render_template(template: &str, context: &TemplateContext) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}') {
+            Some(end) => {
+                rendered.push_str(&template_value(context, &after[..end]).unwrap_or_default());
+                rest = &after[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Finds the chapter containing `time` using half-open `[start, end)` ranges,
+/// so a time on a boundary belongs to the chapter that starts there. The last
+/// chapter also includes its end. On overlapping metadata the later-starting
+/// chapter wins; times in a gap or outside all chapters match nothing.
+pub(crate) fn find_current_chapter(chapters: &[Chapter], time: f64) -> Option<&Chapter> {
+    let last_end = chapters.iter().map(|ch| ch.end).fold(f64::NEG_INFINITY, f64::max);
+    chapters
+        .iter()
+        .filter(|ch| ch.start <= time && (time < ch.end || (time == ch.end && ch.end == last_end)))
+        .max_by(|a, b| a.start.total_cmp(&b.start))
+}
+
+/// The chapter to name for `time`. When no chapter contains it, imperfect
+/// boundaries usually left a gap, so the next chapter to start is shown; past
+/// the last chapter, the one ending last.
+pub(crate) fn find_display_chapter(chapters: &[Chapter], time: f64) -> Option<&Chapter> {
+    find_current_chapter(chapters, time)
+        .or_else(|| {
+            chapters
+                .iter()
+                .filter(|ch| ch.start > time)
+                .min_by(|a, b| a.start.total_cmp(&b.start))
+        })
+        .or_else(|| chapters.iter().max_by(|a, b| a.end.total_cmp(&b.end)))
+}
+
+/// Only bare numbers get a "Chapter" prefix; titles that already start with a
+/// chapter word or are named ("Prologue", "Part Two") are kept as they are.
+pub(crate) fn format_chapter_title(title: &str, extra_words: &[String]) -> String {
+    let title = title.trim();
+    if has_chapter_prefix(title, extra_words) {
+        return title.to_string();
+    }
+
+    let number = title.trim_end_matches(['.', ':']);
+    if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) {
+        format!("Chapter {}", number)
+    } else {
+        title.to_string()
+    }
+}
+
+/// "Chapter 34 of 112 — The Siege", or just "Chapter 5 of 80" when the title
+/// is only a number or a chapter word plus a number.
+pub(crate) fn format_numbered_chapter_title(
+    title: &str,
+    number: usize,
+    total: usize,
+    extra_words: &[String],
+) -> String {
+    let numbering = format!("Chapter {} of {}", number, total);
+    let title = title.trim();
+    let name = strip_chapter_word(title, extra_words).map_or(title, strip_chapter_number);
+    if name.chars().all(|c| c.is_ascii_digit() || c.is_whitespace() || c.is_ascii_punctuation()) {
+        numbering
+    } else {
+        format!("{} — {}", numbering, name)
+    }
+}
+
+/// Drops the number and separators after the chapter word, so " 5: The Siege"
+/// becomes "The Siege".
+fn strip_chapter_number(rest: &str) -> &str {
+    let is_separator = |c: char| c.is_whitespace() || matches!(c, ':' | '-' | '.' | '#' | '–' | '—');
+    let rest = rest.trim_start_matches(is_separator);
+    let rest = match rest.trim_start_matches(|c: char| c.is_ascii_digit()) {
+        unnumbered if unnumbered.len() < rest.len() => unnumbered,
+        _ => match rest.split_whitespace().next() {
+            Some(word) if roman_numeral(word.trim_end_matches(['.', ':'])).is_some() => &rest[word.len()..],
+            _ => rest,
+        },
+    };
+    rest.trim_start_matches(is_separator)
+}
+
+/// Whether the title starts with a chapter word (`CHAPTER_WORDS` or the
+/// configured `chapter_prefixes`) followed by a number or separator, so
+/// "Chapter 3" counts but "Chapter House" and "Partly Cloudy" don't.
+pub(crate) fn has_chapter_prefix(title: &str, extra_words: &[String]) -> bool {
+    strip_chapter_word(title.trim_start(), extra_words).is_some()
+}
+
+/// What follows the chapter word, e.g. " 3: The Siege" for
+/// "Chapter 3: The Siege".
+fn strip_chapter_word<'a>(title: &'a str, extra_words: &[String]) -> Option<&'a str> {
+    let lowercase = title.to_lowercase();
+    let extra_words = extra_words.iter().map(|word| word.trim().to_lowercase());
+    CHAPTER_WORDS.iter().map(|word| word.to_string()).chain(extra_words).find_map(|word| {
+        if word.is_empty() || !lowercase.starts_with(&word) {
+            return None;
+        }
+        let rest = title.get(word.len()..)?;
+        let next = rest.trim_start();
+        let is_separated = next.is_empty()
+            || next.starts_with(|c: char| c.is_ascii_digit() || matches!(c, ':' | '-' | '.' | '#' | '–' | '—'))
+            || next
+                .split_whitespace()
+                .next()
+                .is_some_and(|word| roman_numeral(word.trim_end_matches(['.', ':'])).is_some());
+        is_separated.then_some(rest)
+    })
+}
+
+pub(crate) fn format_series(series: &SeriesInfo) -> String {
+    match series.sequence.as_deref().map(str::trim) {
+        Some(sequence) if !sequence.is_empty() => format!("{} #{}", series.name, sequence),
+        _ => series.name.clone(),
+    }
+}
+
+/// Up to `max` genres joined with " / ", leaving out blocked ones, or
+/// "Unknown Genre" when none are left.
+pub(crate) fn format_genres(genres: &[String], max: usize, blocklist: &[String]) -> String {
+    let shown: Vec<&str> = genres
+        .iter()
+        .map(|genre| genre.trim())
+        .filter(|genre| !genre.is_empty() && !blocklist.iter().any(|blocked| blocked.trim().eq_ignore_ascii_case(genre)))
+        .take(max)
+        .collect();
+    if shown.is_empty() {
+        "Unknown Genre".to_string()
+    } else {
+        shown.join(" / ")
+    }
+}
+
+pub(crate) fn truncate_field(value: &str) -> String {
+    if value.chars().count() <= DISCORD_FIELD_MAX_LEN {
+        value.to_string()
+    } else {
+        let mut truncated: String = value.chars().take(DISCORD_FIELD_MAX_LEN - 1).collect();
+        truncated.push('…');
+        truncated
+    }
+}
+
+pub(crate) fn progress_percent(current_time: f64, duration: f64) -> u32 {
+    if duration > 0.0 {
+        (current_time / duration * 100.0).round().clamp(0.0, 100.0) as u32
+    } else {
+        0
+    }
+}
+
+pub(crate) fn format_timestamp(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, secs) = (total / 3600, (total % 3600) / 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, secs)
+    } else {
+        format!("{}:{:02}", minutes, secs)
+    }
+}
+
+/// A rough length like "4h 12m", "58m" or "45s". Negative values show as
+/// "0m" and anything from 100 hours up as "99h+".
+pub(crate) fn format_duration(seconds: f64) -> String {
+    if seconds.is_nan() || seconds < 1.0 {
+        return "0m".to_string();
+    }
+    let total = seconds as u64;
+    let (hours, minutes) = (total / 3600, (total % 3600) / 60);
+    if hours > 99 {
+        "99h+".to_string()
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", total)
+    }
+}
+
+/// Words that put a series number right after them, compared in lower case
+/// without a trailing dot. Besides English this covers German, French,
+/// Spanish and Italian titles.
+const BOOK_NUMBER_MARKERS: &[&str] = &["book", "volume", "vol", "no", "band", "tome", "livre", "tomo", "libro"];
+
+/// The series number in a title such as "Mistborn Vol. 2", "Series #3" or
+/// "Dune Book IV", normalized to "Book 2" for the cover search.
+pub(crate) fn extract_book_number(title: &str) -> Option<String> {
+    let words: Vec<&str> = title
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | ';' | '(' | ')' | '[' | ']'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.iter().enumerate().find_map(|(index, word)| {
+        let next = words.get(index + 1).copied();
+        let number = match word.strip_prefix('#') {
+            Some("") => next,
+            Some(number) => Some(number),
+            None if BOOK_NUMBER_MARKERS.contains(&word.trim_end_matches('.').to_lowercase().as_str()) => next,
+            None => None,
+        };
+        number.and_then(parse_book_number).map(|number| format!("Book {}", number))
+    })
+}
+
+/// "2", "02", "2.5" or a roman numeral up to XXXIX.
+fn parse_book_number(word: &str) -> Option<String> {
+    let word = word.trim_end_matches('.');
+    let (whole, fraction) = word.split_once('.').unwrap_or((word, ""));
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if !whole.is_empty() && is_digits(whole) && is_digits(fraction) {
+        let whole: u32 = whole.parse().ok()?;
+        return Some(if fraction.is_empty() { whole.to_string() } else { format!("{}.{}", whole, fraction) });
+    }
+    roman_numeral(word).map(|number| number.to_string())
+}
+
+fn roman_numeral(word: &str) -> Option<u32> {
+    if word.is_empty() || !word.chars().all(|c| matches!(c, 'I' | 'V' | 'X')) {
+        return None;
+    }
+    let values: Vec<i32> = word
+        .chars()
+        .map(|c| match c {
+            'I' => 1,
+            'V' => 5,
+            _ => 10,
+        })
+        .collect();
+    let total: i32 = values
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| if values.get(index + 1).is_some_and(|&next| next > value) { -value } else { value })
+        .sum();
+    let mut canonical = String::new();
+    let mut rest = total;
+    for (value, numeral) in [(10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")] {
+        while rest >= value {
+            canonical.push_str(numeral);
+            rest -= value;
+        }
+    }
+    (canonical == word).then_some(total as u32)
+}
+
+pub(crate) fn get_base_title(title: &str) -> &str {
+    if let Some(idx) = title.find(|c| c == ':' || c == '(') {
+        title[..idx].trim()
+    } else {
+        title.trim()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template_fills_known_placeholders() {
+        let context = TemplateContext {
+            title: "Dune Messiah".to_string(),
+            series: "Dune".to_string(),
+            sequence: "2".to_string(),
+            progress_percent: 42,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_template("{title} ({series} #{sequence}) {progress_percent}%", &context),
+            "Dune Messiah (Dune #2) 42%"
+        );
+    }
+
+    #[test]
+    fn render_template_drops_unknown_placeholders() {
+        let context = TemplateContext {
+            author: "Frank Herbert".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(render_template("by {author}{nope} {", &context), "by Frank Herbert {");
+    }
+
+    #[test]
+    fn format_chapter_title_avoids_double_prefix() {
+        let cases = [
+            ("Chapter 12", "Chapter 12"),
+            ("Chapter12", "Chapter12"),
+            ("chapter twelve", "chapter twelve"),
+            ("Ch. 3", "Ch. 3"),
+            ("Kapitel zwölf", "Kapitel zwölf"),
+            ("Kapitel 4", "Kapitel 4"),
+            ("Hoofdstuk 7", "Hoofdstuk 7"),
+            ("Capítulo 2", "Capítulo 2"),
+            ("Capitulo dos", "Capitulo dos"),
+            ("12", "Chapter 12"),
+            ("  7. ", "Chapter 7"),
+            ("003", "Chapter 003"),
+            ("Prologue", "Prologue"),
+            ("Epilogue", "Epilogue"),
+            ("Part Two", "Part Two"),
+            ("Proloog", "Proloog"),
+        ];
+        for (title, expected) in cases {
+            assert_eq!(format_chapter_title(title, &[]), expected, "title: {:?}", title);
+        }
+    }
+
+    #[test]
+    fn format_numbered_chapter_title_skips_meaningless_titles() {
+        assert_eq!(format_numbered_chapter_title("The Siege", 34, 112, &[]), "Chapter 34 of 112 — The Siege");
+        assert_eq!(format_numbered_chapter_title("5", 5, 80, &[]), "Chapter 5 of 80");
+        assert_eq!(format_numbered_chapter_title("Chapter 5", 5, 80, &[]), "Chapter 5 of 80");
+        assert_eq!(format_numbered_chapter_title("Kapitel 5.", 5, 80, &[]), "Chapter 5 of 80");
+        assert_eq!(format_numbered_chapter_title("Chapter 5: The Siege", 5, 80, &[]), "Chapter 5 of 80 — The Siege");
+        let parts = ["Part".to_string()];
+        assert_eq!(format_numbered_chapter_title("Part 2", 9, 80, &parts), "Chapter 9 of 80");
+        assert_eq!(format_numbered_chapter_title("Part of Me", 9, 80, &parts), "Chapter 9 of 80 — Part of Me");
+    }
+
+    #[test]
+    fn format_series_omits_missing_sequence() {
+        let series = |sequence: Option<&str>| SeriesInfo {
+            name: "The Stormlight Archive".to_string(),
+            sequence: sequence.map(str::to_string),
+        };
+        assert_eq!(format_series(&series(Some("2"))), "The Stormlight Archive #2");
+        assert_eq!(format_series(&series(Some(""))), "The Stormlight Archive");
+        assert_eq!(format_series(&series(None)), "The Stormlight Archive");
+    }
+
+    fn chapter(title: &str, start: f64, end: f64) -> Chapter {
+        Chapter {
+            title: title.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn find_current_chapter_uses_half_open_ranges() {
+        let chapters = vec![
+            chapter("1", 0.0, 1800.0),
+            chapter("2", 1800.0, 3600.0),
+            chapter("3", 3600.0, 5400.0),
+        ];
+        let title_at = |time| find_current_chapter(&chapters, time).map(|ch| ch.title.as_str());
+
+        assert_eq!(title_at(0.0), Some("1"));
+        assert_eq!(title_at(1799.9), Some("1"));
+        assert_eq!(title_at(1800.0), Some("2"));
+        assert_eq!(title_at(3600.0), Some("3"));
+        assert_eq!(title_at(5400.0), Some("3"));
+        assert_eq!(title_at(-1.0), None);
+        assert_eq!(title_at(5400.1), None);
+    }
+
+    #[test]
+    fn adjacent_chapters_never_both_match_a_boundary() {
+        let chapters = vec![chapter("Chapter 3", 1800.0, 3600.0), chapter("Chapter 4", 3600.0, 5400.0)];
+        for time in [1800.0, 3599.999, 3600.0, 3600.001] {
+            let matching = chapters
+                .iter()
+                .filter(|ch| find_current_chapter(&chapters, time).is_some_and(|found| std::ptr::eq(found, *ch)))
+                .count();
+            assert_eq!(matching, 1, "time: {}", time);
+        }
+        assert_eq!(find_current_chapter(&chapters, 3600.0).map(|ch| ch.title.as_str()), Some("Chapter 4"));
+    }
+
+    #[test]
+    fn find_current_chapter_handles_gaps_and_overlaps() {
+        let chapters = vec![
+            chapter("1", 0.0, 100.0),
+            chapter("2", 90.0, 200.0),
+            chapter("3", 250.0, 300.0),
+        ];
+        let title_at = |time| find_current_chapter(&chapters, time).map(|ch| ch.title.as_str());
+
+        assert_eq!(title_at(95.0), Some("2"));
+        assert_eq!(title_at(225.0), None);
+        assert_eq!(title_at(250.0), Some("3"));
+    }
+
+    #[test]
+    fn find_display_chapter_looks_ahead_in_gaps() {
+        let chapters = vec![
+            chapter("1", 10.0, 100.0),
+            chapter("2", 120.0, 200.0),
+            chapter("3", 250.0, 300.0),
+        ];
+        let title_at = |time| find_display_chapter(&chapters, time).map(|ch| ch.title.as_str());
+
+        assert_eq!(title_at(50.0), Some("1"));
+        assert_eq!(title_at(0.0), Some("1"));
+        assert_eq!(title_at(100.0), Some("2"));
+        assert_eq!(title_at(225.0), Some("3"));
+        assert_eq!(title_at(350.0), Some("3"));
+        assert!(find_display_chapter(&[], 50.0).is_none());
+    }
+
+    #[test]
+    fn format_duration_rounds_down_and_caps() {
+        assert_eq!(format_duration(4.0 * 3600.0 + 12.0 * 60.0 + 30.0), "4h 12m");
+        assert_eq!(format_duration(3600.0), "1h 0m");
+        assert_eq!(format_duration(58.0 * 60.0 + 59.0), "58m");
+        assert_eq!(format_duration(45.9), "45s");
+        assert_eq!(format_duration(0.5), "0m");
+        assert_eq!(format_duration(-30.0), "0m");
+        assert_eq!(format_duration(f64::NAN), "0m");
+        assert_eq!(format_duration(99.0 * 3600.0 + 59.0 * 60.0), "99h 59m");
+        assert_eq!(format_duration(100.0 * 3600.0), "99h+");
+        assert_eq!(format_duration(f64::INFINITY), "99h+");
+    }
+
+    #[test]
+    fn progress_percent_clamps_odd_metadata() {
+        assert_eq!(progress_percent(1890.0, 3000.0), 63);
+        assert_eq!(progress_percent(3100.0, 3000.0), 100);
+        assert_eq!(progress_percent(-5.0, 3000.0), 0);
+        assert_eq!(progress_percent(100.0, 0.0), 0);
+    }
+
+    #[test]
+    fn format_genres_joins_and_skips_blocked() {
+        let genres = ["Fantasy", "Erotica", "Science Fiction", "Adventure", "Epic"].map(String::from);
+        let blocklist = ["erotica".to_string()];
+        assert_eq!(format_genres(&genres, 3, &blocklist), "Fantasy / Science Fiction / Adventure");
+        assert_eq!(format_genres(&genres, 1, &[]), "Fantasy");
+        assert_eq!(format_genres(&genres[1..2], 3, &blocklist), "Unknown Genre");
+        assert_eq!(format_genres(&[], 3, &[]), "Unknown Genre");
+    }
+
+    #[test]
+    fn truncate_field_respects_char_boundaries() {
+        let long = "é".repeat(200);
+        let truncated = truncate_field(&long);
+        assert_eq!(truncated.chars().count(), DISCORD_FIELD_MAX_LEN);
+        assert!(truncated.ends_with('…'));
+        assert_eq!(truncate_field("short"), "short");
+    }
+
+    #[test]
+    fn has_chapter_prefix_ignores_case_and_leading_space() {
+        assert!(has_chapter_prefix("  CHAPTER 3", &[]));
+        assert!(has_chapter_prefix("Kapitel 7", &[]));
+        assert!(!has_chapter_prefix("The Chapter House", &[]));
+    }
+
+    #[test]
+    fn has_chapter_prefix_needs_a_number_or_separator() {
+        let extra = ["Part".to_string(), " Rozdział ".to_string()];
+        for title in ["Chapter", "Chapter 3", "Chapter IV", "Chapter: The Siege", "Ch.3", "Part 2", "rozdział 5"] {
+            assert!(has_chapter_prefix(title, &extra), "title: {:?}", title);
+        }
+        for title in ["Chapter House", "Chapters of Life", "Partly Cloudy", "Part of Me", "Chapter Inside"] {
+            assert!(!has_chapter_prefix(title, &extra), "title: {:?}", title);
+        }
+        assert!(!has_chapter_prefix("Part 2", &[]));
+    }
+
+    #[test]
+    fn get_base_title_strips_subtitle_and_edition() {
+        assert_eq!(get_base_title("Dune: Deluxe Edition"), "Dune");
+        assert_eq!(get_base_title("Mistborn (Book 1)"), "Mistborn");
+        assert_eq!(get_base_title("  Hyperion "), "Hyperion");
+    }
+
+    #[test]
+    fn extract_book_number_reads_digits_after_book() {
+        assert_eq!(extract_book_number("Wheel of Time, Book 10: Crossroads"), Some("Book 10".to_string()));
+        assert_eq!(extract_book_number("Standalone Novel"), None);
+    }
+
+    #[test]
+    fn extract_book_number_understands_other_markers() {
+        let number = |title| extract_book_number(title);
+        assert_eq!(number("Mistborn Vol. 2"), Some("Book 2".to_string()));
+        assert_eq!(number("Mistborn Volume 02: The Well of Ascension"), Some("Book 2".to_string()));
+        assert_eq!(number("The Expanse #3"), Some("Book 3".to_string()));
+        assert_eq!(number("The Expanse # 3.5"), Some("Book 3.5".to_string()));
+        assert_eq!(number("Dune Messiah (Dune No. 2)"), Some("Book 2".to_string()));
+        assert_eq!(number("The Dark Tower IV, Book IV"), Some("Book 4".to_string()));
+        assert_eq!(number("Harry Potter, Band 7"), Some("Book 7".to_string()));
+        assert_eq!(number("Les Rougon-Macquart, Tome XIX"), Some("Book 19".to_string()));
+        assert_eq!(number("Wheel of Time Book 10"), Some("Book 10".to_string()));
+        assert_eq!(number("No Country for Old Men"), None);
+        assert_eq!(number("Book IIII"), None);
+        assert_eq!(number("Volume Control"), None);
+    }
+}
@@ -0,0 +1,273 @@
+//! `--init`: asks for the basic settings and writes config.json, like the
+//! installer's config generation.
+
+use reqwest::Client;
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::abs_client::{with_tls_settings, AbsClient};
+use crate::config::{is_toml, normalize_url, parse_config, DEFAULT_REQUEST_TIMEOUT_SECS};
+use crate::convert::config_to_toml;
+
+pub const DEFAULT_DISCORD_CLIENT_ID: &str = "1283070638088650752";
+
+/// Prompts for the Audiobookshelf login and Discord settings, checks the
+/// login against the server and writes them to `config_file`. Other keys of
+/// an existing config are kept, and overwriting it needs confirmation.
+/// Returns whether the file was written.
+pub async fn run_init(
+    config_file: &str,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<bool, Box<dyn Error>> {
+    let existing: Option<Map<String, Value>> = match fs::read_to_string(config_file) {
+        Ok(contents) => Some(
+            parse_config(config_file, &contents)
+                .map_err(|e| format!("{} exists but can't be parsed ({}), fix or move it first", config_file, e))?,
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let current = |key: &str| {
+        existing
+            .as_ref()
+            .and_then(|config| config.get(key))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+
+    writeln!(output, "Please enter the following information:")?;
+    let audiobookshelf_url = loop {
+        let url = prompt(
+            input,
+            output,
+            "Audiobookshelf URL (Don't forget to include port if not reverse proxying)",
+            current("audiobookshelf_url").as_deref(),
+        )?;
+        match normalize_url("audiobookshelf_url", &url) {
+            Ok(url) => break url,
+            Err(e) => writeln!(output, "{}", e)?,
+        }
+    };
+    let current_token = current("audiobookshelf_token");
+    let audiobookshelf_token = loop {
+        let label = "Audiobookshelf API Key (Find this when clicking on your user in settings)";
+        let token = match &current_token {
+            Some(token) => {
+                let entered = prompt(input, output, &format!("{}, leave empty to keep the current one", label), None)?;
+                if entered.is_empty() { token.clone() } else { entered }
+            }
+            None => prompt(input, output, label, None)?,
+        };
+        if !token.is_empty() {
+            break token;
+        }
+        writeln!(output, "The API key can't be empty")?;
+    };
+    let discord_client_id = loop {
+        let default = current("discord_client_id").unwrap_or_else(|| DEFAULT_DISCORD_CLIENT_ID.to_string());
+        let id = prompt(input, output, "Discord Client ID", Some(&default))?;
+        if !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) {
+            break id;
+        }
+        writeln!(output, "The Discord Client ID is a number, e.g. {}", DEFAULT_DISCORD_CLIENT_ID)?;
+    };
+    let show_chapters_default = match existing.as_ref().and_then(|config| config.get("show_chapters")) {
+        Some(Value::Bool(true)) => "yes",
+        _ => "no",
+    };
+    let show_chapters = prompt(input, output, "Show chapters instead of genres? (yes/no)", Some(show_chapters_default))?
+        .to_lowercase()
+        .starts_with('y');
+
+    let mut tls = Map::new();
+    let mut client = Client::new();
+    loop {
+        let abs = AbsClient::new(
+            &client,
+            &audiobookshelf_url,
+            &audiobookshelf_token,
+            Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECS),
+        );
+        let e = match abs.me().await {
+            Ok(user) => {
+                writeln!(output, "Logged in to Audiobookshelf as {}", user.username)?;
+                break;
+            }
+            Err(e) => e,
+        };
+        // Asked once: a server with a private certificate can't be reached
+        // at all until one of the TLS options is set.
+        if e.is_certificate_error() && audiobookshelf_url.starts_with("https://") && tls.is_empty() {
+            writeln!(output, "Audiobookshelf's certificate isn't trusted: {}", e)?;
+            let ca_file = prompt(
+                input,
+                output,
+                "Path to your CA certificate (PEM) to trust, leave empty to skip",
+                None,
+            )?;
+            if !ca_file.is_empty() {
+                tls.insert("tls_ca_file".to_string(), json!(ca_file));
+            } else if confirm(
+                input,
+                output,
+                "Skip certificate checks instead? Anyone who can intercept the connection could read your API key",
+                false,
+            )? {
+                tls.insert("tls_accept_invalid_certs".to_string(), json!(true));
+            }
+            if !tls.is_empty() {
+                let ca_file = tls.get("tls_ca_file").and_then(Value::as_str);
+                let accept_invalid_certs = tls.contains_key("tls_accept_invalid_certs");
+                match with_tls_settings(Client::builder(), ca_file, accept_invalid_certs) {
+                    Ok(builder) => {
+                        client = builder.build()?;
+                        continue;
+                    }
+                    Err(e) => writeln!(output, "{}", e)?,
+                }
+            }
+        } else {
+            writeln!(output, "Couldn't log in to Audiobookshelf: {}", e)?;
+        }
+        if !confirm(input, output, "Save the config anyway?", false)? {
+            return Ok(false);
+        }
+        break;
+    }
+
+    let mut config = existing.clone().unwrap_or_default();
+    let answers = json!({
+        "discord_client_id": discord_client_id,
+        "audiobookshelf_url": audiobookshelf_url,
+        "audiobookshelf_token": audiobookshelf_token,
+        "show_chapters": show_chapters,
+    });
+    config.extend(answers.as_object().cloned().unwrap_or_default());
+    config.extend(tls);
+
+    if let Some(existing) = &existing {
+        let changes = config_diff(existing, &config);
+        if changes.is_empty() {
+            writeln!(output, "{} is already up to date", config_file)?;
+            return Ok(false);
+        }
+        if confirm(input, output, "Show the changes?", true)? {
+            for line in changes {
+                writeln!(output, "{}", line)?;
+            }
+        }
+        if !confirm(input, output, &format!("Overwrite {}?", config_file), false)? {
+            return Ok(false);
+        }
+    }
+
+    write_config(config_file, &config)?;
+    writeln!(output, "Config file created at: {}", config_file)?;
+    Ok(true)
+}
+
+/// `-`/`+` lines for every top-level key that differs. The token's value
+/// is never shown.
+pub(crate) fn config_diff(old: &Map<String, Value>, new: &Map<String, Value>) -> Vec<String> {
+    let shown = |key: &str, value: &Value| {
+        if key == "audiobookshelf_token" {
+            "<hidden>".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    let mut lines = Vec::new();
+    for (key, value) in new {
+        match old.get(key) {
+            Some(old_value) if old_value == value => {}
+            Some(old_value) => {
+                lines.push(format!("- \"{}\": {}", key, shown(key, old_value)));
+                lines.push(format!("+ \"{}\": {}", key, shown(key, value)));
+            }
+            None => lines.push(format!("+ \"{}\": {}", key, shown(key, value))),
+        }
+    }
+    lines
+}
+
+/// Writes the config as TOML or JSON, depending on the file's extension,
+/// readable only by the current user.
+pub fn write_config(config_file: &str, config: &Map<String, Value>) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(config_file);
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let contents = if is_toml(config_file) {
+        config_to_toml(config)?
+    } else {
+        serde_json::to_string_pretty(config)?
+    };
+    fs::write(path, contents)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+fn prompt(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    prompt: &str,
+    default: Option<&str>,
+) -> Result<String, io::Error> {
+    match default {
+        Some(default) => write!(output, "{} [{}]: ", prompt, default)?,
+        None => write!(output, "{}: ", prompt)?,
+    }
+    output.flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input ended before the config was complete"));
+    }
+    match (line.trim(), default) {
+        ("", Some(default)) => Ok(default.to_string()),
+        (answer, _) => Ok(answer.to_string()),
+    }
+}
+
+fn confirm(input: &mut impl BufRead, output: &mut impl Write, question: &str, default: bool) -> Result<bool, io::Error> {
+    let default = if default { "yes" } else { "no" };
+    let answer = prompt(input, output, &format!("{} (yes/no)", question), Some(default))?;
+    Ok(answer.to_lowercase().starts_with('y'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_diff_lists_changes_and_hides_the_token() {
+        let old = json!({ "audiobookshelf_url": "http://nas:13378", "audiobookshelf_token": "old", "show_chapters": true });
+        let new = json!({
+            "audiobookshelf_url": "https://abs.example.com",
+            "audiobookshelf_token": "new",
+            "show_chapters": true,
+            "discord_client_id": "1"
+        });
+
+        let diff = config_diff(old.as_object().unwrap(), new.as_object().unwrap());
+        assert_eq!(
+            diff,
+            vec![
+                "- \"audiobookshelf_token\": <hidden>",
+                "+ \"audiobookshelf_token\": <hidden>",
+                "- \"audiobookshelf_url\": \"http://nas:13378\"",
+                "+ \"audiobookshelf_url\": \"https://abs.example.com\"",
+                "+ \"discord_client_id\": \"1\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,14 @@
+pub mod abs_client;
+pub mod cache;
+pub mod config;
+pub mod convert;
+pub mod covers;
+pub mod discord;
+pub mod error;
+pub mod format;
+pub mod init;
+pub mod presence;
+pub mod processes;
+pub mod socket;
+pub mod status;
+pub mod update;
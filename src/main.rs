@@ -1,452 +1,587 @@
-use discord_rich_presence::{activity, DiscordIpcClient, DiscordIpc};
-use futures::future::join_all;
-use serde::Deserialize;
+use audiobookshelf_discord_rpc::abs_client::{build_http_client, AbsClient};
+use audiobookshelf_discord_rpc::cache::{load_cover_cache, save_cover_cache};
+use audiobookshelf_discord_rpc::config::{
+    config_modified_time, cover_cache_path, default_config_path, load_config, poll_intervals, request_timeout,
+    resolve_config_path, ConfigSource, LogFormat, CONFIG_FILE_NAME,
+};
+use audiobookshelf_discord_rpc::covers::get_cover_path;
+use audiobookshelf_discord_rpc::discord::{use_discord_ipc_dir, wait_for_discord, DiscordSink, DryRunSink, PresenceSink};
+use audiobookshelf_discord_rpc::error::{redact_url, AppError};
+use audiobookshelf_discord_rpc::convert::config_to_toml;
+use audiobookshelf_discord_rpc::init::{run_init, write_config};
+use audiobookshelf_discord_rpc::presence::{is_quiet_time, set_activity, PresenceState};
+use audiobookshelf_discord_rpc::processes::ProcessWatch;
+use audiobookshelf_discord_rpc::socket::spawn_sockets;
+use audiobookshelf_discord_rpc::status::{spawn_status_server, SharedStatus, Status, DEFAULT_STATUS_BIND};
+use audiobookshelf_discord_rpc::update::{spawn_update_check, CURRENT_VERSION};
+use chrono::Local;
+use log::{error, info, warn};
+use std::env;
 use std::fs;
-use std::time::Duration;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
 use tokio::time;
-use reqwest::Client;
-use url::Url;
-use std::env;
-use std::time::{SystemTime, UNIX_EPOCH};
-use log::{info, error};
 use env_logger;
 
-const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
-const TIME_OFFSET_CORRECTION: f64 = -16.0;
+/// How long `test` waits for Discord unless `discord_wait_timeout_secs` is set.
+const DISCORD_TEST_TIMEOUT_SECS: u64 = 10;
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    discord_client_id: String,
-    audiobookshelf_url: String,
-    audiobookshelf_token: String,
-    show_chapters: Option<bool>,
-}
+/// Poll interval while every account's token is rejected. A new token only
+/// arrives with a config change, which is picked up on the next poll anyway.
+const AUTH_RETRY_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
-struct Book {
-    name: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct ReleaseInfo {
-    tag_name: String,
+enum CliCommand {
+    Run { source: ConfigSource, cache: Option<String>, dry_run: bool },
+    ValidateConfig { source: ConfigSource },
+    Test { source: ConfigSource, cache: Option<String> },
+    ClearCache { source: ConfigSource, cache: Option<String>, item: Option<String> },
+    Init { config_file: String },
+    ConvertConfig { source: ConfigSource },
+    Version,
+    Help,
 }
 
-#[derive(Debug, Deserialize)]
-struct ListeningSessionsResponse {
-    sessions: Vec<Session>,
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let command = parse_args()?;
+    prepare_discord_ipc(&command);
+    tokio::runtime::Runtime::new()?.block_on(run(command))
 }
 
-#[derive(Debug, Deserialize)]
-#[allow(non_snake_case)]
-struct Session {
-    displayTitle: String,
-    displayAuthor: String,
-    currentTime: f64,
-    duration: f64,
-    mediaMetadata: MediaMetadata,
-    libraryItemId: String,
-    chapters: Option<Vec<Chapter>>,
-    libraryItem: Option<LibraryItem>,
-}
-
-#[derive(Debug, Deserialize)]
-struct MediaMetadata {
-    genres: Vec<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Chapter {
-    title: String,
-    start: f64,
-    end: f64,
-}
-
-#[derive(Debug, Deserialize)]
-struct LibraryItem {
-    media: Option<Media>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Media {
-    chapters: Option<Vec<Chapter>>,
+/// Points the Discord client at the IPC socket before the runtime starts its
+/// worker threads, since that is done through the environment.
+fn prepare_discord_ipc(command: &CliCommand) {
+    let (CliCommand::Run { source, dry_run: false, .. } | CliCommand::Test { source, .. }) = command else {
+        return;
+    };
+    let configured = env::var("DISCORD_IPC_PATH")
+        .ok()
+        .or_else(|| load_config(source).ok()?.discord_ipc_path);
+    use_discord_ipc_dir(configured.as_deref());
 }
 
-#[derive(Debug, Deserialize)]
-struct LibraryItemResponse {
-    media: MediaResponse,
-}
+async fn run(command: CliCommand) -> Result<(), Box<dyn std::error::Error>> {
+    let (source, cache, dry_run) = match command {
+        CliCommand::Run { source, cache, dry_run } => (source, cache, dry_run),
+        CliCommand::ValidateConfig { source } => {
+            init_logger(&source)?;
+            let valid = validate_config(&source).await;
+            std::process::exit(if valid { 0 } else { 1 });
+        }
+        CliCommand::Test { source, cache } => {
+            init_logger(&source)?;
+            let passed = run_checks(&source, cache).await;
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        CliCommand::ClearCache { source, cache, item } => {
+            init_logger(&source)?;
+            clear_cache(&source, cache, item)?;
+            return Ok(());
+        }
+        CliCommand::ConvertConfig { source } => {
+            let config = source.parse(&source.read()?)?;
+            let Some(config_file) = source.path() else {
+                print!("{}", config_to_toml(&config)?);
+                return Ok(());
+            };
+            let toml_file = Path::new(config_file).with_extension("toml").display().to_string();
+            if Path::new(&toml_file).exists() {
+                return Err(format!("{} already exists, not overwriting it", toml_file).into());
+            }
+            write_config(&toml_file, &config)?;
+            println!("Wrote {}, start with -c {} to use it", toml_file, toml_file);
+            return Ok(());
+        }
+        CliCommand::Init { config_file } => {
+            init_logger(&ConfigSource::File(config_file.clone()))?;
+            run_init(&config_file, &mut io::stdin().lock(), &mut io::stdout()).await?;
+            return Ok(());
+        }
+        CliCommand::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), CURRENT_VERSION);
+            return Ok(());
+        }
+        CliCommand::Help => {
+            print_usage();
+            return Ok(());
+        }
+    };
 
-#[derive(Debug, Deserialize)]
-struct MediaResponse {
-    chapters: Vec<Chapter>,
-}
+    init_logger(&source)?;
 
-#[derive(Debug)]
-struct PlaybackState {
-    last_api_time: SystemTime,
-    last_position: f64,
-    is_playing: bool,
-}
+    info!("Using config from {}", source);
 
-#[derive(Debug)]
-struct TimingInfo {
-    last_api_time: Option<SystemTime>,
-    last_position: Option<f64>,
-}
+    let mut config = load_config(&source)?;
+    let mut client = build_http_client(&config)?;
+    let mut config_modified = config_modified_time(&source);
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let update_check_disabled = env::var("ABS_RPC_NO_UPDATE_CHECK").is_ok_and(|value| value == "1");
+    if !config.check_for_updates.unwrap_or(true) || update_check_disabled {
+        info!("Update check disabled, running version {}", CURRENT_VERSION);
+    } else {
+        spawn_update_check(&client);
+    }
 
-    let client = Client::new();
+    let cache_path = cache.map(PathBuf::from).unwrap_or_else(|| cover_cache_path(&config, &source));
+    let mut cover_cache = load_cover_cache(&cache_path);
+    let (mut poll_interval, mut idle_poll_interval, mut idle_polls_before_backoff) = poll_intervals(&config);
 
-    if let Some(latest_version) = check_for_update(&client).await? {
-        info!(
-            "A new version is available: {}. You're currently running version {}.",
-            latest_version, CURRENT_VERSION
-        );
-        info!("Please re-run the installer or visit https://github.com/0xGingi/audiobookshelf-discord-rpc/releases to download the latest version.");
+    let mut discord: Box<dyn PresenceSink> = if dry_run {
+        info!("Dry run: not connecting to Discord, activity will be printed instead");
+        Box::new(DryRunSink)
     } else {
-        info!("You're running the latest version: {}", CURRENT_VERSION);
+        Box::new(DiscordSink::new(&config))
+    };
+    let timeout = config.discord_wait_timeout_secs.map(Duration::from_secs);
+    wait_for_discord(discord.as_mut(), timeout).await?;
+
+    let mut state = PresenceState::default();
+    let status = SharedStatus::default();
+    if let Some(port) = config.status_port {
+        let bind = config.status_bind.as_deref().unwrap_or(DEFAULT_STATUS_BIND);
+        spawn_status_server(bind, port, status.clone()).await?;
+    }
+    let mut idle_polls: u32 = 0;
+    let mut in_quiet_hours = false;
+    let mut process_watch = ProcessWatch::default();
+    let mut suppressed_by: Option<String> = None;
+    let mut auth_backoff = false;
+
+    let socket_wake = Arc::new(Notify::new());
+    let mut socket_tasks = spawn_sockets(&config, &socket_wake);
+
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    {
+        let reload_requested = reload_requested.clone();
+        let socket_wake = socket_wake.clone();
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                reload_requested.store(true, Ordering::Relaxed);
+                socket_wake.notify_one();
+            }
+        });
     }
 
-    let config_file = parse_args()?;
-    info!("Using config file: {}", config_file);
+    loop {
+        let modified = config_modified_time(&source);
+        if reload_requested.swap(false, Ordering::Relaxed) || modified != config_modified {
+            config_modified = modified;
+            match load_config(&source) {
+                Ok(new_config) => {
+                    info!("Reloaded config from {}", source);
+                    if !dry_run && new_config.discord_client_id != config.discord_client_id {
+                        info!("Discord client ID changed, reconnecting");
+                        discord = Box::new(DiscordSink::new(&new_config));
+                    }
+                    if new_config.discord_ipc_path != config.discord_ipc_path {
+                        warn!("discord_ipc_path changed, restart to use the new socket");
+                    }
+                    let http_changed = new_config.request_timeout_secs != config.request_timeout_secs
+                        || new_config.connect_timeout_secs != config.connect_timeout_secs
+                        || new_config.tls_ca_file != config.tls_ca_file
+                        || new_config.tls_accept_invalid_certs != config.tls_accept_invalid_certs;
+                    if http_changed {
+                        match build_http_client(&new_config) {
+                            Ok(new_client) => client = new_client,
+                            Err(e) => error!("Couldn't apply the new HTTP settings, keeping the current ones: {}", e),
+                        }
+                    }
+                    config = new_config;
+                    (poll_interval, idle_poll_interval, idle_polls_before_backoff) = poll_intervals(&config);
+                    state.timing.active_account = None;
+                    state.timing.account_positions.clear();
+                    state.timing.account_auth_failed.clear();
+                    for task in socket_tasks.drain(..) {
+                        task.abort();
+                    }
+                    socket_tasks = spawn_sockets(&config, &socket_wake);
+                }
+                Err(e) => error!("Ignoring invalid config in {}, keeping the current one: {}", source, e),
+            }
+        }
 
-    let config = load_config(&config_file)?;
-    let mut discord = DiscordIpcClient::new(&config.discord_client_id)?;
-    discord.connect()?;
-    info!("Audiobookshelf Discord RPC Connected!");
+        if let Some(quiet_hours) = &config.quiet_hours {
+            let quiet = is_quiet_time(quiet_hours, Local::now().time())?;
+            if quiet && !in_quiet_hours {
+                info!("Entering quiet hours ({} - {}), hiding activity", quiet_hours.start, quiet_hours.end);
+                if let Err(e) = discord.clear() {
+                    error!("Error clearing activity: {}", e);
+                }
+            } else if !quiet && in_quiet_hours {
+                info!("Leaving quiet hours, resuming activity updates");
+            }
+            in_quiet_hours = quiet;
+        }
 
-    let mut playback_state = PlaybackState {
-        last_api_time: SystemTime::now(),
-        last_position: 0.0,
-        is_playing: false,
-    };
-    let mut current_book: Option<Book> = None;
-    let mut timing_info = TimingInfo {
-        last_api_time: None,
-        last_position: None,
-    };
+        if in_quiet_hours {
+            time::sleep(idle_poll_interval).await;
+            continue;
+        }
 
-    loop {
-        if let Err(e) = set_activity(
+        let suppress_names = config.suppress_when_running.as_deref().unwrap_or_default();
+        let running = process_watch.running(suppress_names, poll_interval).map(str::to_string);
+        if running != suppressed_by {
+            match &running {
+                Some(name) => {
+                    info!("{} is running, hiding activity", name);
+                    if let Err(e) = discord.clear() {
+                        error!("Error clearing activity: {}", e);
+                    }
+                }
+                None => info!("No suppressing program running anymore, resuming activity updates"),
+            }
+            suppressed_by = running;
+        }
+        if suppressed_by.is_some() {
+            time::sleep(poll_interval).await;
+            continue;
+        }
+
+        // Once connected, fall through so the current activity is pushed right away.
+        if let Some(retry_in) = discord.reconnect() {
+            status.lock().unwrap().discord_connected = false;
+            time::sleep(retry_in).await;
+            continue;
+        }
+
+        let result = set_activity(
             &client,
             &config,
-            &mut discord,
-            &mut playback_state,
-            &mut current_book,
-            &mut timing_info,
+            discord.as_mut(),
+            &mut state,
+            &mut cover_cache,
         )
-        .await
-        {
-            error!("Error setting activity: {}", e);
+        .await;
+        update_status(&status, &state, &result);
+        if let Err(e) = result {
+            match e {
+                AppError::DiscordIo(_) => {
+                    warn!("Lost connection to Discord: {}", e);
+                    continue;
+                }
+                e if e.is_timeout() => warn!(
+                    "Audiobookshelf didn't answer within {}s, trying again next poll",
+                    request_timeout(&config).as_secs()
+                ),
+                e if e.is_abs() => error!("Couldn't get playback from Audiobookshelf: {}", e),
+                e => error!("Error setting activity: {}", e),
+            }
         }
-        time::sleep(Duration::from_secs(15)).await;
-    }
-}
 
-fn parse_args() -> Result<String, Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    if let Some(index) = args.iter().position(|arg| arg == "-c") {
-        if index + 1 < args.len() {
-            Ok(args[index + 1].clone())
+        if state.playback.is_playing {
+            if idle_polls >= idle_polls_before_backoff {
+                info!("Playback detected, polling every {} seconds", poll_interval.as_secs());
+            }
+            idle_polls = 0;
         } else {
-            Err("Error: missing argument for -c option".into())
+            idle_polls = idle_polls.saturating_add(1);
+            if idle_polls == idle_polls_before_backoff {
+                info!("Nothing playing, slowing down to every {} seconds", idle_poll_interval.as_secs());
+            }
         }
-    } else {
-        Ok("config.json".to_string())
-    }
-}
 
-fn load_config(config_file: &str) -> Result<Config, Box<dyn std::error::Error>> {
-    let config_str = fs::read_to_string(config_file)?;
-    let config: Config = serde_json::from_str(&config_str)?;
-    Ok(config)
-}
-
-#[allow(non_snake_case)]
-async fn set_activity(
-    client: &Client,
-    config: &Config,
-    discord: &mut DiscordIpcClient,
-    playback_state: &mut PlaybackState,
-    current_book: &mut Option<Book>,
-    timing_info: &mut TimingInfo,
-) -> Result<(), Box<dyn std::error::Error>> {
-
-    let sessions_url = format!(
-        "{}/api/me/listening-sessions?itemsPerPage=1", 
-        config.audiobookshelf_url
-    );
-    
-    let resp = client
-        .get(&sessions_url)
-        .bearer_auth(&config.audiobookshelf_token)
-        .send()
-        .await?
-        .json::<ListeningSessionsResponse>()
-        .await?;
-
-    if resp.sessions.is_empty() {
-        info!("No active listening session");
-        discord.clear_activity()?;
-        return Ok(());
-    }
-
-    let session = &resp.sessions[0];
-    
-    if timing_info.last_position.is_none() {
-        playback_state.is_playing = false;
-        discord.clear_activity()?;
-        timing_info.last_position = Some(session.currentTime);
-        timing_info.last_api_time = Some(SystemTime::now());
-        return Ok(());
-    }
-
-    let current_time = session.currentTime;
-    
-    if let (Some(last_time), Some(last_api_time)) = (timing_info.last_position, timing_info.last_api_time) {
-        let elapsed = SystemTime::now().duration_since(last_api_time).unwrap_or(Duration::from_secs(0));
-        if elapsed.as_secs() >= 2 && (current_time - last_time).abs() < f64::EPSILON {
-            playback_state.is_playing = false;
-            discord.clear_activity()?;
-            timing_info.last_position = Some(current_time);
-            timing_info.last_api_time = Some(SystemTime::now());
-            return Ok(());
-        } else if (current_time - last_time).abs() > f64::EPSILON {
-            playback_state.is_playing = true;
+        let mut sleep_for = if idle_polls >= idle_polls_before_backoff {
+            idle_poll_interval
+        } else {
+            poll_interval
+        };
+        if let Some(chapter_ends_at) = state.playback.chapter_ends_at {
+            if let Ok(until_chapter_end) = chapter_ends_at.duration_since(SystemTime::now()) {
+                sleep_for = sleep_for.min(until_chapter_end + Duration::from_secs(1));
+            }
+        }
+        if state.timing.all_auth_failed() != auth_backoff {
+            auth_backoff = !auth_backoff;
+            if auth_backoff {
+                info!("Retrying Audiobookshelf every {} seconds until the token works", AUTH_RETRY_INTERVAL.as_secs());
+            }
+        }
+        if auth_backoff {
+            sleep_for = sleep_for.max(AUTH_RETRY_INTERVAL);
+        }
+        tokio::select! {
+            _ = time::sleep(sleep_for) => {}
+            _ = socket_wake.notified() => {
+                idle_polls = 0;
+            }
         }
     }
+}
 
-    if !playback_state.is_playing {
-        discord.clear_activity()?;
-        timing_info.last_position = Some(current_time);
-        timing_info.last_api_time = Some(SystemTime::now());
-        return Ok(());
+fn update_status(status: &Mutex<Status>, state: &PresenceState, result: &Result<(), AppError>) {
+    let mut status = status.lock().unwrap();
+    status.current_book = state.current_book.as_ref().map(|book| book.name.clone());
+    status.playing = state.playback.is_playing;
+    status.discord_connected = !matches!(result, Err(AppError::DiscordIo(_)));
+    if !matches!(result, Err(e) if e.is_abs()) {
+        status.last_successful_poll = Some(chrono::Utc::now().to_rfc3339());
     }
+}
 
-    let library_item_url = format!(
-        "{}/api/items/{}?include=chapters", 
-        config.audiobookshelf_url,
-        session.libraryItemId
-    );
-    
-    let library_item: LibraryItemResponse = client
-        .get(&library_item_url)
-        .bearer_auth(&config.audiobookshelf_token)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let genres = session.mediaMetadata.genres.get(0).map(|s| s.as_str()).unwrap_or("Unknown Genre");
-    
-    let now = SystemTime::now();
-
-    let large_text = if config.show_chapters.unwrap_or(false) {
-        if let Some(current_chapter) = library_item.media.chapters.iter().find(|ch| {
-            current_time >= ch.start && current_time <= ch.end
-        }) {
-            if current_chapter.title.to_lowercase().contains("chapter") {
-                current_chapter.title.to_string()
-            } else {
-                format!("Chapter {}", current_chapter.title)
+/// Sets up logging as "text" (default) or "json", one object per line, from
+/// ABS_RPC_LOG_FORMAT or the config's `log_format`. Runs before `load_config`
+/// so its warnings are logged, which is why the config is only peeked at.
+fn init_logger(source: &ConfigSource) -> Result<(), Box<dyn std::error::Error>> {
+    let log_format = match env::var("ABS_RPC_LOG_FORMAT").ok().as_deref() {
+        Some("text") => LogFormat::Text,
+        Some("json") => LogFormat::Json,
+        Some(other) => return Err(format!("Invalid ABS_RPC_LOG_FORMAT: expected \"text\" or \"json\", got \"{}\"", other).into()),
+        None => {
+            let configured = source
+                .read()
+                .ok()
+                .and_then(|config_str| source.parse(&config_str).ok())
+                .and_then(|config| config.get("log_format").cloned());
+            match configured {
+                Some(value) => serde_json::from_value(value)
+                    .map_err(|_| "Invalid config: log_format must be \"text\" or \"json\"")?,
+                None => LogFormat::Text,
             }
-        } else {
-            genres.to_string()
         }
-    } else {
-        genres.to_string()
     };
 
-    let book_name = &session.displayTitle;
-    let author = &session.displayAuthor;
-    let duration = session.duration;
-
-    if current_book.as_ref().map_or(true, |book| book.name != *book_name) {
-        *current_book = Some(Book {
-            name: book_name.clone(),
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if log_format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{}", line)
         });
-        *playback_state = PlaybackState {
-            last_api_time: SystemTime::now(),
-            last_position: 0.0,
-            is_playing: false,
-        };
     }
+    builder.init();
+    Ok(())
+}
 
-    let current_position = if playback_state.is_playing {
-        let elapsed = now
-            .duration_since(playback_state.last_api_time)
-            .unwrap_or(Duration::from_secs(0))
-            .as_secs_f64();
-        
-        current_time + elapsed + TIME_OFFSET_CORRECTION
-    } else {
-        current_time
+fn parse_args() -> Result<CliCommand, Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(CliCommand::Help);
+    }
+    if args.iter().any(|arg| arg == "-v" || arg == "--version") {
+        return Ok(CliCommand::Version);
+    }
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let option_value = |option: &str| match args.iter().position(|arg| arg == option) {
+        Some(index) if index + 1 < args.len() => Ok(Some(args[index + 1].clone())),
+        Some(_) => Err(format!("Error: missing argument for {} option", option)),
+        None => Ok(None),
     };
-
-    let mut activity_builder = if playback_state.is_playing {
-        let now_secs = now.duration_since(UNIX_EPOCH)?.as_secs() as i64;
-        let current_pos = current_position.max(0.0) as i64;
-        let total_dur = duration.max(0.0) as i64;
-
-        let start_time = now_secs.saturating_sub(current_pos);
-        let end_time = now_secs.saturating_add(total_dur.saturating_sub(current_pos));
-
-        activity::Activity::new()
-            .details(book_name)
-            .state(author)
-            .timestamps(
-                activity::Timestamps::new()
-                    .start(start_time)
-                    .end(end_time)
-            )
-            .activity_type(activity::ActivityType::Listening)
-    } else {
-        activity::Activity::new()
-            .details(book_name)
-            .state(author)
-            .activity_type(activity::ActivityType::Listening)
+    let source = match (option_value("-c")?, option_value("--config-json")?) {
+        (Some(_), Some(_)) => return Err("Error: -c and --config-json can't be used together".into()),
+        (Some(path), None) if path == "-" => {
+            let mut json = String::new();
+            io::stdin().read_to_string(&mut json)?;
+            ConfigSource::Stdin(json)
+        }
+        (Some(path), None) => ConfigSource::File(path),
+        (None, Some(json)) => ConfigSource::Inline(json),
+        (None, None) => ConfigSource::File(resolve_config_path().display().to_string()),
     };
-
-    let cover_url = get_cover_path(client, config, book_name, author).await?;
-
-    if let Some(ref url) = cover_url {
-        activity_builder = activity_builder.assets(
-            activity::Assets::new()
-                .large_image(url)
-                .large_text(&large_text)
-        );
+    if args.iter().any(|arg| arg == "--init" || arg == "init") {
+        return match source {
+            ConfigSource::File(config_file) => Ok(CliCommand::Init { config_file }),
+            _ => Err("Error: --init needs a config file, not stdin or --config-json".into()),
+        };
+    }
+    if args.iter().any(|arg| arg == "--convert-config") {
+        return Ok(CliCommand::ConvertConfig { source });
+    }
+    let cache = option_value("--cache")?;
+    if args.get(1).is_some_and(|arg| arg == "clear-cache") || args.iter().any(|arg| arg == "--clear-cache") {
+        let item = option_value("--item")?;
+        return Ok(CliCommand::ClearCache { source, cache, item });
+    }
+    if args.get(1).is_some_and(|arg| arg == "test") || args.iter().any(|arg| arg == "--test") {
+        return Ok(CliCommand::Test { source, cache });
+    }
+    if args.iter().any(|arg| arg == "--validate-config") {
+        return Ok(CliCommand::ValidateConfig { source });
     }
+    Ok(CliCommand::Run { source, cache, dry_run })
+}
 
-    discord.set_activity(activity_builder)?;
-
-    if let (Some(last_time), Some(last_api_time)) = (timing_info.last_position, timing_info.last_api_time) {
-        if (current_time - last_time).abs() > f64::EPSILON {
-            let elapsed = SystemTime::now()
-                .duration_since(last_api_time)
-                .unwrap_or(Duration::from_secs(0));
-            info!(
-                "API position updated: previous={:.2}s, current={:.2}s, time since last update={:.2}s",
-                last_time,
-                current_time,
-                elapsed.as_secs_f64()
-            );
+/// Loads the config and checks every account's token against `/api/me`.
+async fn validate_config(source: &ConfigSource) -> bool {
+    let config = match load_config(source) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}: {}", source, e);
+            return false;
+        }
+    };
+    let client = match build_http_client(&config) {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Couldn't create the HTTP client: {}", e);
+            return false;
+        }
+    };
+    let timeout = request_timeout(&config);
+    let mut valid = true;
+    for account in &config.accounts {
+        let abs = AbsClient::for_account(&client, account, timeout);
+        match abs.me().await {
+            Ok(user) => info!("Logged in to {} as {}", redact_url(abs.base_url()), user.username),
+            Err(e) => {
+                error!("{} ({})", e, redact_url(abs.base_url()));
+                valid = false;
+            }
         }
     }
-    
-    timing_info.last_position = Some(current_time);
-    timing_info.last_api_time = Some(SystemTime::now());
-
-    Ok(())
+    if valid {
+        info!("{} is valid", source);
+    }
+    valid
 }
 
-async fn get_cover_path(
-    client: &Client,
-    config: &Config,
-    title: &str,
-    author: &str,
-) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let search_title = if let Some(book_num) = extract_book_number(title) {
-        format!("{} {}", get_base_title(title), book_num)
-    } else {
-        get_base_title(title).to_string()
+/// `test`: goes through everything a real run needs once, printing a line
+/// per check, and returns whether all of them passed. Nothing is shown on
+/// Discord.
+async fn run_checks(source: &ConfigSource, cache: Option<String>) -> bool {
+    let mut passed = true;
+    let mut report = |ok: bool, message: String| {
+        println!("[{}] {}", if ok { " ok " } else { "FAIL" }, message);
+        passed &= ok;
     };
 
-    let providers = vec![
-        "audible",
-        "google",
-        "audible.jp",
-        "openlibrary",
-        "itunes",
-        "audible.ca",
-        "audible.uk",
-        "audible.au",
-        "audible.fr",
-        "audible.de",
-        "audible.it",
-        "audible.in",
-        "audible.es",
-        "fantlab",
-    ];
-
-    let futures = providers.iter().map(|provider| {
-        let client = client.clone();
-        let config = config;
-        let title = search_title.clone();
-        let author = author.to_string();
-        async move {
-            let url = Url::parse_with_params(
-                &format!("{}/api/search/covers", config.audiobookshelf_url),
-                &[("title", title.as_str()), ("author", author.as_str()), ("provider", *provider)],
-            )?;
-            let resp: CoverResponse = client
-                .get(url)
-                .bearer_auth(&config.audiobookshelf_token)
-                .send()
-                .await?
-                .json()
-                .await?;
-            if let Some(cover_url) = resp.results.get(0) {
-                return Ok(Some(cover_url.clone()));
-            }
-            Ok(None)
+    let config = match load_config(source) {
+        Ok(config) => config,
+        Err(e) => {
+            report(false, format!("Couldn't load the config from {}: {}", source, e));
+            return false;
         }
-    });
-
-    let results: Vec<Result<Option<String>, Box<dyn std::error::Error>>> = join_all(futures).await;
-    for result in results {
-        if let Ok(Some(url)) = result {
-            return Ok(Some(url));
+    };
+    report(true, format!("Loaded the config from {}", source));
+    let client = match build_http_client(&config) {
+        Ok(client) => client,
+        Err(e) => {
+            report(false, format!("HTTP client: {}", e));
+            return false;
         }
-    }
-
-    Ok(None)
-}
+    };
 
-fn extract_book_number(title: &str) -> Option<String> {
-    if let Some(idx) = title.find("Book ") {
-        let after_book = &title[idx + 5..];
-        if let Some(end) = after_book.find(|c: char| !c.is_numeric()) {
-            return Some(format!("Book {}", &after_book[..end]));
+    let cache_path = cache.map(PathBuf::from).unwrap_or_else(|| cover_cache_path(&config, source));
+    let mut cover_cache = load_cover_cache(&cache_path);
+    let timeout = request_timeout(&config);
+    for account in &config.accounts {
+        let abs = AbsClient::for_account(&client, account, timeout);
+        let server = redact_url(abs.base_url());
+        match abs.me().await {
+            Ok(user) => report(true, format!("Logged in to {} as {}", server, user.username)),
+            Err(e) => {
+                report(false, format!("Logging in to {}: {}", server, e));
+                continue;
+            }
+        }
+        let session = match abs.latest_sessions(1).await {
+            Ok(sessions) => sessions.into_iter().next(),
+            Err(e) => {
+                report(false, format!("Listening sessions from {}: {}", server, e));
+                continue;
+            }
+        };
+        let Some(session) = session else {
+            report(true, format!("No listening sessions on {} yet, skipping the cover lookup", server));
+            continue;
+        };
+        report(true, format!("Latest session on {}: {}", server, session.displayTitle));
+        match get_cover_path(
+            &abs,
+            &mut cover_cache,
+            &config.cover_providers,
+            &session.libraryItemId,
+            &session.displayTitle,
+            &session.displayAuthor,
+        )
+        .await
+        {
+            Ok(Some(url)) => report(true, format!("Cover found: {}", url)),
+            Ok(None) => report(true, "No cover found, the presence will be shown without one".to_string()),
+            Err(e) => report(false, format!("Cover lookup: {}", e)),
         }
     }
-    None
-}
 
-fn get_base_title(title: &str) -> &str {
-    if let Some(idx) = title.find(|c| c == ':' || c == '(') {
-        title[..idx].trim()
-    } else {
-        title.trim()
+    let mut discord = DiscordSink::new(&config);
+    let timeout = Duration::from_secs(config.discord_wait_timeout_secs.unwrap_or(DISCORD_TEST_TIMEOUT_SECS));
+    match wait_for_discord(&mut discord, Some(timeout)).await {
+        Ok(()) => report(true, "Connected to Discord".to_string()),
+        Err(e) => report(false, format!("Connecting to Discord: {}", e)),
     }
+    passed
 }
 
-#[derive(Debug, Deserialize)]
-struct CoverResponse {
-    results: Vec<String>,
-}
-
-async fn check_for_update(client: &Client) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let url = "https://api.github.com/repos/0xGingi/audiobookshelf-discord-rpc/releases/latest";
-    let resp = client
-        .get(url)
-        .header("User-Agent", "Audiobookshelf-Discord-RPC")
-        .send()
-        .await?;
-
-    if !resp.status().is_success() {
-        return Err(format!("GitHub API request failed with status: {}", resp.status()).into());
+/// Deletes the cover cache the service would use, or only the entries for
+/// one item when `item` is given.
+fn clear_cache(
+    source: &ConfigSource,
+    cache: Option<String>,
+    item: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cache_path = match cache {
+        Some(cache) => PathBuf::from(cache),
+        None => cover_cache_path(&load_config(source)?, source),
+    };
+    if !cache_path.exists() {
+        println!("No cover cache at {}", cache_path.display());
+        return Ok(());
     }
+    let Some(item) = item else {
+        fs::remove_file(&cache_path)?;
+        println!("Deleted {}", cache_path.display());
+        return Ok(());
+    };
 
-    let release_info: ReleaseInfo = resp.json().await?;
-    let latest_version = release_info.tag_name.trim_start_matches('v');
+    let mut cover_cache = load_cover_cache(&cache_path);
+    match cover_cache.remove_item(&item) {
+        0 => println!("Nothing cached for {} in {}", item, cache_path.display()),
+        removed => {
+            save_cover_cache(&cover_cache)?;
+            let entries = if removed == 1 { "entry" } else { "entries" };
+            println!("Removed {} {} for {} from {}", removed, entries, item, cache_path.display());
+        }
+    }
+    Ok(())
+}
 
-    if latest_version != CURRENT_VERSION {
-        Ok(Some(latest_version.to_string()))
-    } else {
-        Ok(None)
+fn print_usage() {
+    println!("{} {}", env!("CARGO_PKG_NAME"), CURRENT_VERSION);
+    println!("{}", env!("CARGO_PKG_DESCRIPTION"));
+    println!();
+    println!("Usage: {} [OPTIONS]", env!("CARGO_PKG_NAME"));
+    println!();
+    println!("Options:");
+    println!("  -c <FILE>             Path to the config file (default: see below), - reads JSON from stdin");
+    println!("  --config-json <JSON>  Use this JSON as the whole config instead of a file");
+    println!("  --dry-run             Print the activity instead of sending it to Discord");
+    println!("  --cache <FILE>        Keep the cover cache in this file instead of urls.json next to the config");
+    println!("  --validate-config     Check the config and Audiobookshelf login, then exit");
+    println!("  test, --test          Check the login, sessions, cover lookup and Discord once, then exit");
+    println!("  clear-cache           Delete the cover cache, or with --item <ID> only that item's covers");
+    println!("  --init                Create or update the config file interactively");
+    println!("  --convert-config      Write a commented config.toml next to the JSON config");
+    println!("  -v, --version         Print the version and exit");
+    println!("  -h, --help            Print this help and exit");
+    println!();
+    println!("Config file, when -c isn't given:");
+    if let Some(path) = default_config_path() {
+        println!("  {}", path.display());
     }
-}
\ No newline at end of file
+    println!("  {} in the working directory, if the one above doesn't exist", CONFIG_FILE_NAME);
+    println!();
+    println!("Environment:");
+    println!("  ABS_RPC_NO_UPDATE_CHECK=1    Skip the startup check for new releases");
+    println!("  ABS_RPC_LOG_FORMAT=json      Log one JSON object per line (or \"text\")");
+    println!("  ABS_RPC_URL, ABS_RPC_TOKEN   Override audiobookshelf_url and audiobookshelf_token");
+    println!("  ABS_RPC_TOKEN_FILE           Read the token from this file instead");
+    println!("  ABS_RPC_<FIELD>              Override any other config field, e.g. ABS_RPC_SHOW_CHAPTERS=true");
+}
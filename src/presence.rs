@@ -0,0 +1,1121 @@
+//! Turning the current listening session into a Discord activity.
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use log::{debug, error, info, warn};
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+use crate::abs_client::{
+    corrected_position, is_session_stale, matches_filter, pick_session, playback_speed, session_key,
+    session_passes_filters, AbsClient, LibraryItemResponse, Session, SESSIONS_PER_POLL,
+};
+use crate::cache::CoverCache;
+use crate::config::{
+    request_timeout, AccountConfig, BlockAction, ChapterDisplay, Config, NarratorDisplay, QuietHours, ScheduleWindow,
+    TimeDisplay, TimestampMode, DEFAULT_MAX_GENRES, DEFAULT_PAUSED_TIMEOUT_SECS, DEFAULT_PAUSE_AFTER_POLLS,
+    DEFAULT_LIBRARY_ITEM_TTL_SECS, DEFAULT_SEEK_THRESHOLD_SECS, DEFAULT_STALE_SESSION_SECS,
+};
+use crate::covers::{get_abs_cover_url, get_cover_path};
+use crate::discord::{ActivityUpdate, PresenceSink, PresenceType};
+use crate::error::{redact_url, AppError};
+use crate::format::{
+    find_current_chapter, find_display_chapter, format_chapter_title, format_duration, format_genres,
+    format_numbered_chapter_title, format_series, format_timestamp, progress_percent, render_template, truncate_field, TemplateContext,
+};
+
+pub(crate) const MAX_BUTTONS: usize = 2;
+pub(crate) const MAX_BUTTON_LABEL_LEN: usize = 32;
+pub(crate) const MAX_BUTTON_URL_LEN: usize = 512;
+/// A session duration this far from the cached one means the book was
+/// re-encoded or replaced, so its chapters are fetched again.
+pub(crate) const LIBRARY_ITEM_DURATION_TOLERANCE_SECS: f64 = 5.0;
+
+#[derive(Debug)]
+pub struct Book {
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct PlaybackState {
+    pub last_api_time: SystemTime,
+    pub last_position: f64,
+    pub is_playing: bool,
+    pub paused_since: Option<SystemTime>,
+    pub chapter_ends_at: Option<SystemTime>,
+    /// Consecutive polls without forward progress.
+    pub stalled_polls: u32,
+}
+
+/// The last library item fetched, reused while the same book plays.
+#[derive(Debug)]
+pub struct CachedLibraryItem {
+    pub item_id: String,
+    /// The session's duration when the item was fetched.
+    pub duration: f64,
+    pub fetched_at: SystemTime,
+    pub item: LibraryItemResponse,
+}
+
+#[derive(Debug)]
+pub struct TimingInfo {
+    pub last_api_time: Option<SystemTime>,
+    pub last_position: Option<f64>,
+    pub account_positions: Vec<Option<f64>>,
+    pub account_auth_failed: Vec<bool>,
+    pub active_account: Option<usize>,
+    pub playback_speed: Option<f64>,
+    pub filtered_sessions: HashSet<String>,
+    /// Items already reported as having no duration, keyed by `session_key`.
+    pub zero_duration_items: HashSet<String>,
+    /// Items whose library item request already failed, so it's logged once.
+    pub unavailable_items: HashSet<String>,
+    /// Last seen `currentTime` of every open session, keyed by `session_key`.
+    pub session_positions: HashMap<String, f64>,
+    /// Whether the last poll fell inside a `schedule` window.
+    pub scheduled_off: bool,
+    /// Seconds listened to the current session, keyed by `session_key`,
+    /// for `min_play_seconds`.
+    pub played: Option<(String, f64)>,
+    /// When polls started failing, to clear the activity once the server
+    /// has been unreachable for longer than `stale_session_secs`.
+    pub unreachable_since: Option<SystemTime>,
+}
+
+/// Everything `set_activity` remembers between polls.
+#[derive(Debug)]
+pub struct PresenceState {
+    pub playback: PlaybackState,
+    pub current_book: Option<Book>,
+    pub timing: TimingInfo,
+    pub library_item: Option<CachedLibraryItem>,
+}
+
+impl Default for PresenceState {
+    fn default() -> Self {
+        PresenceState {
+            playback: PlaybackState::new(),
+            current_book: None,
+            timing: TimingInfo {
+                last_api_time: None,
+                last_position: None,
+                account_positions: Vec::new(),
+                account_auth_failed: Vec::new(),
+                active_account: None,
+                playback_speed: None,
+                filtered_sessions: HashSet::new(),
+                zero_duration_items: HashSet::new(),
+                unavailable_items: HashSet::new(),
+                session_positions: HashMap::new(),
+                scheduled_off: false,
+                played: None,
+                unreachable_since: None,
+            },
+            library_item: None,
+        }
+    }
+}
+
+impl TimingInfo {
+    /// Whether the last poll was turned away by every account's server, so
+    /// there's no point polling at the normal rate.
+    pub fn all_auth_failed(&self) -> bool {
+        !self.account_auth_failed.is_empty() && self.account_auth_failed.iter().all(|&failed| failed)
+    }
+}
+
+impl PlaybackState {
+    fn new() -> Self {
+        PlaybackState {
+            last_api_time: SystemTime::now(),
+            last_position: 0.0,
+            is_playing: false,
+            paused_since: None,
+            chapter_ends_at: None,
+            stalled_polls: 0,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+pub async fn set_activity(
+    client: &Client,
+    config: &Config,
+    sink: &mut dyn PresenceSink,
+    state: &mut PresenceState,
+    cover_cache: &mut CoverCache,
+) -> Result<(), AppError> {
+    let PresenceState {
+        playback: playback_state,
+        current_book,
+        timing: timing_info,
+        library_item: cached_item,
+    } = state;
+
+    let mut sessions = Vec::with_capacity(config.accounts.len());
+    let mut errors = Vec::new();
+    let mut auth_failed = false;
+    timing_info.account_auth_failed.resize(config.accounts.len(), false);
+    let stale_after = Duration::from_secs(config.stale_session_secs.unwrap_or(DEFAULT_STALE_SESSION_SECS));
+    let mut session_positions = HashMap::new();
+    let timeout = request_timeout(config);
+    let abs_clients: Vec<AbsClient> = config
+        .accounts
+        .iter()
+        .map(|account| AbsClient::for_account(client, account, timeout))
+        .collect();
+    for (index, account) in config.accounts.iter().enumerate() {
+        match abs_clients[index].latest_sessions(SESSIONS_PER_POLL).await {
+            Ok(open_sessions) => {
+                let open_sessions: Vec<Session> = open_sessions
+                    .into_iter()
+                    .filter(|session| {
+                        !is_session_stale(session.last_update(), SystemTime::now(), stale_after)
+                            && session_passes_filters(config, session, &mut timing_info.filtered_sessions)
+                    })
+                    .collect();
+                for session in &open_sessions {
+                    session_positions.insert(session_key(session), session.currentTime);
+                }
+                let session = pick_session(open_sessions, &timing_info.session_positions);
+                if timing_info.account_auth_failed[index] {
+                    info!("Audiobookshelf authentication for {} is working again", redact_url(&account.audiobookshelf_url));
+                    timing_info.account_auth_failed[index] = false;
+                }
+                sessions.push(session);
+            }
+            Err(e) if e.is_auth_failure() => {
+                if !timing_info.account_auth_failed[index] {
+                    error!("{} ({})", e, redact_url(&account.audiobookshelf_url));
+                    timing_info.account_auth_failed[index] = true;
+                }
+                auth_failed = true;
+                sessions.push(None);
+            }
+            Err(e) => {
+                sessions.push(None);
+                errors.push((account, e));
+            }
+        }
+    }
+
+    timing_info.session_positions = session_positions;
+
+    timing_info.account_positions.resize(config.accounts.len(), None);
+    let mut playing_account = None;
+    for (index, session) in sessions.iter().enumerate() {
+        let current = session.as_ref().map(|s| s.currentTime);
+        let previous = std::mem::replace(&mut timing_info.account_positions[index], current);
+        if let (Some(previous), Some(current)) = (previous, current) {
+            if playing_account.is_none() && (current - previous).abs() > f64::EPSILON {
+                playing_account = Some(index);
+            }
+        }
+    }
+
+    let selected = playing_account
+        .or(timing_info.active_account.filter(|&index| sessions[index].is_some()))
+        .or_else(|| sessions.iter().position(Option::is_some));
+
+    let (account_index, session) = match selected.and_then(|index| sessions[index].as_ref().map(|s| (index, s))) {
+        Some(selected) => {
+            timing_info.unreachable_since = None;
+            selected
+        }
+        None => {
+            if !errors.is_empty() {
+                // The activity is kept through a short outage, but a book
+                // shouldn't stay up for good once the server is gone.
+                match timing_info.unreachable_since {
+                    Some(since) if SystemTime::now().duration_since(since).unwrap_or_default() >= stale_after => {
+                        debug!("Audiobookshelf unreachable for over {}s, clearing activity", stale_after.as_secs());
+                        playback_state.is_playing = false;
+                        sink.clear()?;
+                    }
+                    Some(_) => {}
+                    None => timing_info.unreachable_since = Some(SystemTime::now()),
+                }
+                return Err(errors.remove(0).1);
+            }
+            timing_info.unreachable_since = None;
+            if !auth_failed {
+                info!("No active listening session");
+            }
+            sink.clear()?;
+            return Ok(());
+        }
+    };
+
+    for (account, e) in errors {
+        error!("Error fetching listening sessions from {}: {}", redact_url(&account.audiobookshelf_url), e);
+    }
+
+    let account = &config.accounts[account_index];
+    let abs = &abs_clients[account_index];
+    timing_info.active_account = Some(account_index);
+
+    if timing_info.last_position.is_none() {
+        playback_state.is_playing = false;
+        sink.clear()?;
+        timing_info.last_position = Some(session.currentTime);
+        timing_info.last_api_time = Some(SystemTime::now());
+        return Ok(());
+    }
+
+    let is_podcast = session.mediaType.as_deref() == Some("podcast");
+    let Some(config) = config.for_media_type(is_podcast) else {
+        debug!("Not showing {}, its media type is disabled", session.displayTitle);
+        playback_state.is_playing = false;
+        sink.clear()?;
+        timing_info.last_position = Some(session.currentTime);
+        timing_info.last_api_time = Some(SystemTime::now());
+        return Ok(());
+    };
+    let config = config.as_ref();
+
+    let current_time = if config.use_media_progress.unwrap_or(false) {
+        match abs.media_progress(&session.libraryItemId, session.episodeId.as_deref()).await {
+            Ok(progress) => {
+                let corrected = corrected_position(session, &progress);
+                if (corrected - session.currentTime).abs() > 1.0 {
+                    debug!(
+                        "Media progress corrected position: session={:.2}s, progress={:.2}s",
+                        session.currentTime, corrected
+                    );
+                }
+                corrected
+            }
+            Err(e) => {
+                debug!("Falling back to the session position, media progress unavailable: {}", e);
+                session.currentTime
+            }
+        }
+    } else {
+        session.currentTime
+    };
+    // Woken early because the chapter should have just ended; the server may
+    // not have synced a new position yet, so don't mistake that for a pause.
+    let chapter_boundary_wake = playback_state
+        .chapter_ends_at
+        .take()
+        .is_some_and(|ends_at| ends_at <= SystemTime::now());
+    let mut chapter_lookup_time = current_time;
+    let key = session_key(session);
+    // The last position belongs to another session after a switch.
+    let same_session = timing_info.played.as_ref().is_some_and(|(played_key, _)| *played_key == key);
+    if !same_session {
+        timing_info.played = Some((key, 0.0));
+    }
+
+    if let (Some(last_time), Some(last_api_time)) = (timing_info.last_position, timing_info.last_api_time) {
+        let elapsed = SystemTime::now().duration_since(last_api_time).unwrap_or(Duration::from_secs(0));
+        if chapter_boundary_wake && playback_state.is_playing && (current_time - last_time).abs() < f64::EPSILON {
+            chapter_lookup_time = current_time + elapsed.as_secs_f64();
+        } else if elapsed.as_secs() >= 2 || (current_time - last_time).abs() > f64::EPSILON {
+            if let Some((_, played)) = timing_info.played.as_mut().filter(|_| same_session) {
+                *played += played_between(
+                    last_time,
+                    current_time,
+                    elapsed.as_secs_f64(),
+                    config.playback_speed.unwrap_or_else(|| playback_speed(session)),
+                    config.seek_threshold_seconds.unwrap_or(DEFAULT_SEEK_THRESHOLD_SECS),
+                );
+            }
+            playback_state.is_playing = detect_playing(
+                playback_state.is_playing,
+                &mut playback_state.stalled_polls,
+                last_time,
+                current_time,
+                config.seek_threshold_seconds.unwrap_or(DEFAULT_SEEK_THRESHOLD_SECS),
+                config.pause_after_polls.unwrap_or(DEFAULT_PAUSE_AFTER_POLLS),
+            );
+        }
+    }
+
+    if playback_state.is_playing {
+        playback_state.paused_since = None;
+    } else {
+        let paused_since = *playback_state.paused_since.get_or_insert_with(SystemTime::now);
+        let paused_for = SystemTime::now().duration_since(paused_since).unwrap_or(Duration::from_secs(0));
+        let paused_timeout = config.paused_timeout_secs.unwrap_or(DEFAULT_PAUSED_TIMEOUT_SECS);
+
+        let keep_when_paused = match config.clear_on_pause {
+            Some(clear_on_pause) => !clear_on_pause,
+            None => config.show_when_paused.unwrap_or(false),
+        };
+
+        if !keep_when_paused || paused_for.as_secs() >= paused_timeout {
+            sink.clear()?;
+            timing_info.last_position = Some(current_time);
+            timing_info.last_api_time = Some(SystemTime::now());
+            return Ok(());
+        }
+    }
+    let is_paused = !playback_state.is_playing;
+
+    // Playback is still tracked above, so the presence picks up where it is
+    // as soon as the window ends.
+    let scheduled_off = match &config.schedule {
+        Some(schedule) => is_scheduled_off(schedule, Local::now().naive_local())?,
+        None => false,
+    };
+    if scheduled_off != timing_info.scheduled_off {
+        if scheduled_off {
+            info!("Inside a schedule window, hiding activity");
+        } else {
+            info!("Schedule window over, resuming activity updates");
+        }
+        timing_info.scheduled_off = scheduled_off;
+    }
+    if scheduled_off {
+        sink.clear()?;
+        timing_info.last_position = Some(current_time);
+        timing_info.last_api_time = Some(SystemTime::now());
+        return Ok(());
+    }
+
+    if let Some(min_play) = config.min_play_seconds.filter(|&secs| secs > 0) {
+        let played = timing_info.played.as_ref().map_or(0.0, |(_, played)| *played);
+        if played < min_play as f64 {
+            debug!(
+                "Not showing {} yet, listened {:.0}s of the {}s min_play_seconds",
+                session.displayTitle, played, min_play
+            );
+            sink.clear()?;
+            timing_info.last_position = Some(current_time);
+            timing_info.last_api_time = Some(SystemTime::now());
+            return Ok(());
+        }
+    }
+
+    // Decided before any lookup so a blocked title never leaves the machine.
+    let blocked = is_blocklisted(config, session);
+    let privacy_mode = config.privacy_mode.unwrap_or(false);
+    if blocked || privacy_mode {
+        let action = config.blocklist.as_ref().and_then(|blocklist| blocklist.action);
+        if blocked && !privacy_mode && action.unwrap_or(BlockAction::Clear) == BlockAction::Clear {
+            debug!("Not showing a session that matches the blocklist");
+            sink.clear()?;
+        } else {
+            sink.use_client_id(discord_client_id(config, session));
+            sink.set(&private_activity(config, is_podcast))?;
+        }
+        timing_info.last_position = Some(current_time);
+        timing_info.last_api_time = Some(SystemTime::now());
+        return Ok(());
+    }
+
+    let library_item = fetch_library_item(abs, config, session, cached_item, &mut timing_info.unavailable_items).await;
+
+    let genres = format_genres(
+        &session.mediaMetadata.genres,
+        config.max_genres.unwrap_or(DEFAULT_MAX_GENRES),
+        config.genre_blocklist.as_deref().unwrap_or_default(),
+    );
+
+    let now = SystemTime::now();
+
+    let chapters = library_item.as_ref().map(|item| item.media.chapters.as_slice()).unwrap_or_default();
+    let current_chapter = find_current_chapter(chapters, chapter_lookup_time);
+    // Named even between chapters; the chapter timeline only uses an exact match.
+    let shown_chapter = find_display_chapter(chapters, chapter_lookup_time);
+    let chapter_number = shown_chapter
+        .and_then(|shown| chapters.iter().position(|ch| std::ptr::eq(ch, shown)))
+        .map(|index| index + 1);
+    let chapter_prefixes = config.chapter_prefixes.as_deref().unwrap_or_default();
+    let chapter = shown_chapter.map(|shown_chapter| {
+        match chapter_number {
+            Some(number) if config.show_chapter_numbers.unwrap_or(false) => {
+                format_numbered_chapter_title(&shown_chapter.title, number, chapters.len(), chapter_prefixes)
+            }
+            _ => format_chapter_title(&shown_chapter.title, chapter_prefixes),
+        }
+    });
+
+    let book_name = &session.displayTitle;
+    let author = &session.displayAuthor;
+    let duration = session.duration;
+
+    let episode = session.episodeId.as_ref().and_then(|episode_id| {
+        let episodes = &library_item.as_ref()?.media.episodes;
+        episodes.iter().find(|episode| &episode.id == episode_id)
+    });
+    let series = session.mediaMetadata.series.first();
+    // Podcasts have no narrator, the hosts are already the author.
+    let narrator = if is_podcast {
+        String::new()
+    } else {
+        session.mediaMetadata.narrator()
+    };
+
+    if current_book.as_ref().map_or(true, |book| book.name != *book_name) {
+        *current_book = Some(Book {
+            name: book_name.clone(),
+        });
+        *playback_state = PlaybackState::new();
+    }
+
+    let speed = config.playback_speed.unwrap_or_else(|| playback_speed(session));
+    if timing_info.playback_speed != Some(speed) {
+        info!("Playback speed: {}x", speed);
+        timing_info.playback_speed = Some(speed);
+    }
+
+    if (current_time - playback_state.last_position).abs() > f64::EPSILON {
+        if playback_state.is_playing {
+            let elapsed = now
+                .duration_since(playback_state.last_api_time)
+                .unwrap_or(Duration::from_secs(0))
+                .as_secs_f64();
+            let expected = playback_state.last_position + elapsed * speed;
+            let threshold = config.seek_threshold_seconds.unwrap_or(DEFAULT_SEEK_THRESHOLD_SECS);
+            if (current_time - expected).abs() > threshold {
+                info!(
+                    "Seek detected: expected position={:.2}s, reported={:.2}s, resyncing timestamps",
+                    expected, current_time
+                );
+            }
+        }
+        playback_state.last_api_time = now;
+        playback_state.last_position = current_time;
+    }
+
+    let current_position = if playback_state.is_playing {
+        extrapolate_position(current_time, playback_state.last_api_time, now, speed)
+    } else {
+        current_time
+    };
+
+    let template_context = TemplateContext {
+        title: book_name.to_string(),
+        author: author.to_string(),
+        narrator,
+        series: series.map(|series| series.name.clone()).unwrap_or_default(),
+        sequence: series.and_then(|series| series.sequence.clone()).unwrap_or_default(),
+        chapter: chapter.clone().unwrap_or_default(),
+        chapter_number: chapter_number.map(|number| number.to_string()).unwrap_or_default(),
+        chapter_count: if chapters.is_empty() {
+            String::new()
+        } else {
+            chapters.len().to_string()
+        },
+        genre: genres,
+        progress_percent: progress_percent(current_position, duration),
+        current_time,
+        duration,
+        // Based on the extrapolated position, so it stays put while paused.
+        time_left: format_duration((duration - current_position) / speed),
+        podcast_title: if is_podcast {
+            session.mediaMetadata.title.clone().unwrap_or_default()
+        } else {
+            String::new()
+        },
+        episode: episode.and_then(|episode| episode.episode.clone()).unwrap_or_default(),
+        season: episode.and_then(|episode| episode.season.clone()).unwrap_or_default(),
+        library_item_id: session.libraryItemId.clone(),
+        abs_url: button_base_url(config, account),
+    };
+    let details = match &config.details_template {
+        Some(template) => render_template(template, &template_context),
+        None => book_name.to_string(),
+    };
+    let narrator = &template_context.narrator;
+    let (show_author, show_narrator, narrator_display) = config.credits();
+    let credits = if show_narrator && !narrator.is_empty() {
+        match narrator_display {
+            NarratorDisplay::Append if show_author => format!("{} • read by {}", author, narrator),
+            _ => format!("Narrated by {}", narrator),
+        }
+    } else if show_author {
+        author.to_string()
+    } else {
+        String::new()
+    };
+    let state = match (&config.state_template, series) {
+        (Some(template), _) => render_template(template, &template_context),
+        (None, Some(series)) if config.show_series.unwrap_or(false) && credits.is_empty() => format_series(series),
+        (None, Some(series)) if config.show_series.unwrap_or(false) => {
+            format!("{} • {}", credits, format_series(series))
+        }
+        (None, _) => credits,
+    };
+    // "Chapter 5 (3:21 left)", left out when the position isn't inside the
+    // chapter, e.g. a last chapter that ends after the book does.
+    let chapter = match (chapter, shown_chapter) {
+        (Some(chapter), Some(current)) if config.show_chapter_time_left.unwrap_or(false) => {
+            let chapter_end = if duration > 0.0 { current.end.min(duration) } else { current.end };
+            if (current.start..chapter_end).contains(&current_position) {
+                let left = format_timestamp(((chapter_end - current_position) / speed).round());
+                Some(format!("{} ({} left)", chapter, left))
+            } else {
+                Some(chapter)
+            }
+        }
+        (chapter, _) => chapter,
+    };
+    let large_text = match (config.show_chapters.unwrap_or(ChapterDisplay::Genre), &chapter) {
+        (ChapterDisplay::Chapter, Some(chapter)) => chapter.clone(),
+        (ChapterDisplay::Both, Some(chapter)) => format!("{} • {}", chapter, template_context.genre),
+        (ChapterDisplay::None, _) => String::new(),
+        _ => template_context.genre.clone(),
+    };
+    let large_text = match &config.large_text_template {
+        Some(template) => render_template(template, &template_context),
+        None => large_text,
+    };
+    let large_text = if config.show_time_remaining.unwrap_or(false) && duration > 0.0 {
+        let time_left = format!("{} left", template_context.time_left);
+        if large_text.is_empty() { time_left } else { format!("{} • {}", large_text, time_left) }
+    } else {
+        large_text
+    };
+    // An empty state is left out of the activity.
+    let state = if config.show_progress.unwrap_or(false) && duration > 0.0 {
+        if state.is_empty() {
+            format!("{}%", template_context.progress_percent)
+        } else {
+            format!("{} ({}%)", state, template_context.progress_percent)
+        }
+    } else {
+        state
+    };
+    let state = if is_paused {
+        if state.is_empty() {
+            "⏸ Paused".to_string()
+        } else {
+            format!("⏸ {}", state)
+        }
+    } else {
+        state
+    };
+    let details = truncate_field(&details);
+    let state = truncate_field(&state);
+    let large_text = truncate_field(&large_text);
+
+    if duration <= 0.0 && timing_info.zero_duration_items.insert(session_key(session)) {
+        warn!(
+            "Item {} has no duration, showing it without a progress bar; check its metadata in Audiobookshelf",
+            session.libraryItemId
+        );
+    }
+
+    let (start, end) = if playback_state.is_playing && duration > 0.0 {
+        let (range_start, range_end) = match current_chapter {
+            Some(chapter) if config.timestamp_mode == Some(TimestampMode::Chapter) => {
+                playback_state.chapter_ends_at = Some(
+                    now + Duration::from_secs_f64(((chapter.end - current_position) / speed).max(0.0)),
+                );
+                (chapter.start, chapter.end)
+            }
+            _ => (0.0, duration),
+        };
+        let (start_time, end_time) = timeline_timestamps(now, current_position, range_start, range_end, speed);
+
+        match config.time_display.unwrap_or(TimeDisplay::Both) {
+            TimeDisplay::Elapsed => (Some(start_time), None),
+            TimeDisplay::Remaining => (None, Some(end_time)),
+            TimeDisplay::Both => (Some(start_time), Some(end_time)),
+        }
+    } else {
+        (None, None)
+    };
+
+    let buttons = build_buttons(config, account, &template_context);
+
+    // The cover providers only know books, so podcasts rely on the show's
+    // cover from Audiobookshelf.
+    let abs_cover_url = if library_item.is_some() && (is_podcast || config.use_abs_cover.unwrap_or(false)) {
+        let base_url = button_base_url(config, account);
+        get_abs_cover_url(abs, cover_cache, &base_url, &session.libraryItemId).await
+    } else {
+        None
+    };
+    let cover_url = match abs_cover_url {
+        Some(url) => Some(url),
+        None if is_podcast => None,
+        None => {
+            let providers = &config.cover_providers;
+            get_cover_path(abs, cover_cache, providers, &session.libraryItemId, book_name, author).await?
+        }
+    };
+
+    let small_image_text = match &config.small_image_text {
+        Some(text) => text.clone(),
+        None if playback_state.is_playing => "Playing".to_string(),
+        None => "Paused".to_string(),
+    };
+
+    sink.use_client_id(discord_client_id(config, session));
+
+    sink.set(&ActivityUpdate {
+        details,
+        state,
+        large_text,
+        large_image: cover_url,
+        small_image: config.small_image_key.clone(),
+        small_text: small_image_text,
+        start,
+        end,
+        buttons,
+        chapter,
+        activity_type: config
+            .activity_type
+            .as_deref()
+            .and_then(PresenceType::parse)
+            .unwrap_or_default(),
+    })?;
+
+    if let (Some(last_time), Some(last_api_time)) = (timing_info.last_position, timing_info.last_api_time) {
+        if (current_time - last_time).abs() > f64::EPSILON {
+            let elapsed = SystemTime::now()
+                .duration_since(last_api_time)
+                .unwrap_or(Duration::from_secs(0));
+            info!(
+                "API position updated: previous={:.2}s, current={:.2}s, time since last update={:.2}s",
+                last_time,
+                current_time,
+                elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    timing_info.last_position = Some(current_time);
+    timing_info.last_api_time = Some(SystemTime::now());
+
+    Ok(())
+}
+
+/// Guesses whether playback is running from one poll's position change.
+/// Forward progress and backward jumps larger than `seek_threshold` (seeks)
+/// count as playing; only `pause_after_polls` consecutive polls without
+/// forward progress count as paused, so small backward corrections from
+/// switching devices don't flip the state.
+pub(crate) fn detect_playing(
+    was_playing: bool,
+    stalled_polls: &mut u32,
+    previous: f64,
+    current: f64,
+    seek_threshold: f64,
+    pause_after_polls: u32,
+) -> bool {
+    if current - previous > f64::EPSILON || previous - current > seek_threshold {
+        *stalled_polls = 0;
+        return true;
+    }
+    *stalled_polls = stalled_polls.saturating_add(1);
+    was_playing && *stalled_polls < pause_after_polls
+}
+
+/// Real seconds listened between two polls, for `min_play_seconds`. Only
+/// forward progress counts, and no more than could have played in `elapsed`
+/// (plus the seek threshold), so skipping ahead isn't mistaken for listening.
+pub(crate) fn played_between(previous: f64, current: f64, elapsed: f64, speed: f64, seek_threshold: f64) -> f64 {
+    let advance = current - previous;
+    if advance <= 0.0 {
+        return 0.0;
+    }
+    advance.min(elapsed * speed + seek_threshold) / speed
+}
+
+/// Where playback is at `now`, advancing the position last reported at
+/// `reported_at` in real time at the playback speed.
+pub(crate) fn extrapolate_position(position: f64, reported_at: SystemTime, now: SystemTime, speed: f64) -> f64 {
+    let elapsed = now.duration_since(reported_at).unwrap_or_default().as_secs_f64();
+    position + elapsed * speed
+}
+
+/// Discord start/end timestamps (unix seconds) for `position` within
+/// `range_start..range_end`, converted from media time to real time.
+pub(crate) fn timeline_timestamps(
+    now: SystemTime,
+    position: f64,
+    range_start: f64,
+    range_end: f64,
+    speed: f64,
+) -> (i64, i64) {
+    let now_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    let elapsed_real = ((position - range_start) / speed).max(0.0);
+    let remaining_real = ((range_end - position) / speed).max(0.0);
+    ((now_secs - elapsed_real).round() as i64, (now_secs + remaining_real).round() as i64)
+}
+
+pub(crate) fn build_buttons(
+    config: &Config,
+    account: &AccountConfig,
+    template_context: &TemplateContext,
+) -> Vec<(String, String)> {
+    let buttons_config = match &config.buttons {
+        Some(buttons_config) => buttons_config,
+        None => return Vec::new(),
+    };
+
+    let mut buttons = Vec::new();
+    if buttons_config.show_audiobookshelf_link.unwrap_or(false) {
+        buttons.push((
+            "Open in Audiobookshelf".to_string(),
+            format!("{}/item/{}", button_base_url(config, account), template_context.library_item_id),
+        ));
+    }
+    for custom in buttons_config.custom.iter().chain(buttons_config.links.iter().flatten()) {
+        buttons.push((
+            render_template(&custom.label, template_context),
+            render_template(&custom.url, template_context),
+        ));
+    }
+
+    buttons.retain(|(label, url)| {
+        let valid = !label.is_empty()
+            && label.chars().count() <= MAX_BUTTON_LABEL_LEN
+            && url.len() <= MAX_BUTTON_URL_LEN
+            && Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+        if !valid {
+            warn!("Skipping button \"{}\" with invalid label or URL: {}", label, url);
+        }
+        valid
+    });
+    buttons.truncate(MAX_BUTTONS);
+    buttons
+}
+
+/// The session's library item, fetched again only when the book changes, the
+/// cached copy is older than `library_item_ttl_secs` or the duration moved.
+/// Chapters, episode numbers and the Audiobookshelf cover are extras, so a
+/// failed request is logged and the session is shown without them.
+async fn fetch_library_item<'a>(
+    abs: &AbsClient,
+    config: &Config,
+    session: &Session,
+    cached: &'a mut Option<CachedLibraryItem>,
+    unavailable_items: &mut HashSet<String>,
+) -> Option<&'a LibraryItemResponse> {
+    let ttl = Duration::from_secs(config.library_item_ttl_secs.unwrap_or(DEFAULT_LIBRARY_ITEM_TTL_SECS));
+    let item_id = &session.libraryItemId;
+    let same_item = cached.as_ref().filter(|cached| &cached.item_id == item_id);
+    let duration_changed = same_item
+        .is_some_and(|cached| (cached.duration - session.duration).abs() > LIBRARY_ITEM_DURATION_TOLERANCE_SECS);
+    let expired = same_item.is_none_or(|cached| {
+        SystemTime::now().duration_since(cached.fetched_at).map_or(true, |age| age >= ttl)
+    });
+    if duration_changed {
+        debug!("Duration of {} changed, fetching its chapters again", session.displayTitle);
+    }
+
+    if duration_changed || expired {
+        match abs.library_item(item_id).await {
+            Ok(item) => {
+                unavailable_items.remove(item_id);
+                *cached = Some(CachedLibraryItem {
+                    item_id: item_id.clone(),
+                    duration: session.duration,
+                    fetched_at: SystemTime::now(),
+                    item,
+                });
+            }
+            Err(e) => {
+                if unavailable_items.insert(item_id.clone()) {
+                    warn!("Couldn't load item {} from Audiobookshelf, showing it without chapters: {}", item_id, e);
+                } else {
+                    debug!("Item {} is still unavailable: {}", item_id, e);
+                }
+                *cached = None;
+            }
+        }
+    }
+    cached.as_ref().map(|cached| &cached.item)
+}
+
+/// The session's library's entry in `library_client_ids`, or the default
+/// Discord application.
+fn discord_client_id<'a>(config: &'a Config, session: &Session) -> &'a str {
+    session
+        .libraryId
+        .as_ref()
+        .and_then(|library_id| config.library_client_ids.as_ref()?.get(library_id))
+        .unwrap_or(&config.discord_client_id)
+}
+
+/// Whether the title, author, series or genres match the blocklist.
+fn is_blocklisted(config: &Config, session: &Session) -> bool {
+    let Some(blocklist) = &config.blocklist else {
+        return false;
+    };
+    if blocklist.patterns.as_ref().is_none_or(Vec::is_empty) {
+        return false;
+    }
+    let metadata = &session.mediaMetadata;
+    let mut values = vec![session.displayTitle.as_str(), session.displayAuthor.as_str()];
+    values.extend(metadata.title.as_deref());
+    values.extend(metadata.series.iter().map(|series| series.name.as_str()));
+    values.extend(metadata.genres.iter().map(String::as_str));
+    matches_filter(&blocklist.patterns, &values)
+}
+
+/// Says that something is playing without saying what.
+fn private_activity(config: &Config, is_podcast: bool) -> ActivityUpdate {
+    let details = if is_podcast {
+        "Listening to a podcast"
+    } else {
+        "Listening to an audiobook"
+    };
+    ActivityUpdate {
+        details: details.to_string(),
+        activity_type: config
+            .activity_type
+            .as_deref()
+            .and_then(PresenceType::parse)
+            .unwrap_or_default(),
+        ..ActivityUpdate::default()
+    }
+}
+
+/// `buttons.public_url` if set, otherwise the account's own URL.
+fn button_base_url(config: &Config, account: &AccountConfig) -> String {
+    config
+        .buttons
+        .as_ref()
+        .and_then(|buttons| buttons.public_url.as_deref())
+        .unwrap_or(&account.audiobookshelf_url)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Whether `url` points somewhere Discord and other users can't reach: LAN,
+/// loopback, link-local and CGNAT (e.g. Tailscale) addresses, and hostnames
+/// that only resolve locally.
+pub(crate) fn is_private_url(url: &str) -> bool {
+    let host = match Url::parse(url) {
+        Ok(url) => match url.host() {
+            Some(host) => host.to_owned(),
+            None => return false,
+        },
+        Err(_) => return false,
+    };
+    match host {
+        url::Host::Domain(domain) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            !domain.contains('.')
+                || [".local", ".lan", ".home", ".internal", ".home.arpa", ".localhost"]
+                    .iter()
+                    .any(|suffix| domain.ends_with(suffix))
+        }
+        url::Host::Ipv4(ip) => {
+            let is_shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || is_shared
+        }
+        url::Host::Ipv6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback() || ip.is_unspecified() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+pub fn is_quiet_time(quiet_hours: &QuietHours, now: NaiveTime) -> Result<bool, AppError> {
+    let parse = |field: &str, value: &str| {
+        NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| {
+            AppError::ConfigError(format!(
+                "Invalid config: quiet_hours.{} must be a 24h time like \"22:00\" (got \"{}\")",
+                field, value
+            ))
+        })
+    };
+    let start = parse("start", &quiet_hours.start)?;
+    let end = parse("end", &quiet_hours.end)?;
+
+    Ok(if start <= end {
+        now >= start && now < end
+    } else {
+        now >= start || now < end
+    })
+}
+
+/// Whether `now` (local time) falls inside any of the `schedule` windows.
+pub fn is_scheduled_off(schedule: &[ScheduleWindow], now: NaiveDateTime) -> Result<bool, AppError> {
+    let mut scheduled_off = false;
+    for (index, window) in schedule.iter().enumerate() {
+        let parse = |field: &str, value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| {
+                AppError::ConfigError(format!(
+                    "Invalid config: schedule[{}].{} must be a 24h time like \"09:00\" (got \"{}\")",
+                    index, field, value
+                ))
+            })
+        };
+        let from = parse("from", &window.from)?;
+        let to = parse("to", &window.to)?;
+        let days = window
+            .days
+            .iter()
+            .map(|day| {
+                day.parse::<Weekday>().map_err(|_| {
+                    AppError::ConfigError(format!(
+                        "Invalid config: schedule[{}].days has \"{}\", expected one of mon, tue, wed, thu, fri, sat, sun",
+                        index, day
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let on = |day: Weekday| days.is_empty() || days.contains(&day);
+        let (today, time) = (now.weekday(), now.time());
+        scheduled_off |= if from == to {
+            on(today)
+        } else if from < to {
+            on(today) && time >= from && time < to
+        } else {
+            (on(today) && time >= from) || (on(today.pred()) && time < to)
+        };
+    }
+    Ok(scheduled_off)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_fill_abs_url_and_stop_at_two() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "discord_client_id": "1283070638088650752",
+            "audiobookshelf_url": "http://192.168.1.5:13378",
+            "buttons": {
+                "public_url": "https://abs.example.com/",
+                "links": [
+                    { "label": "Listen along", "url": "{abs_url}/item/{libraryItemId}" },
+                    { "label": "Goodreads", "url": "https://www.goodreads.com/search?q={title}" },
+                    { "label": "Third", "url": "https://example.com" }
+                ]
+            }
+        }))
+        .unwrap();
+        let account = AccountConfig {
+            audiobookshelf_url: "http://192.168.1.5:13378".to_string(),
+            audiobookshelf_token: "token".to_string(),
+            audiobookshelf_token_file: None,
+        };
+        let context = TemplateContext {
+            title: "Dune".to_string(),
+            library_item_id: "li_1".to_string(),
+            abs_url: button_base_url(&config, &account),
+            ..Default::default()
+        };
+
+        let buttons = build_buttons(&config, &account, &context);
+        assert_eq!(
+            buttons,
+            vec![
+                ("Listen along".to_string(), "https://abs.example.com/item/li_1".to_string()),
+                ("Goodreads".to_string(), "https://www.goodreads.com/search?q=Dune".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn private_urls_cover_lan_hosts_and_addresses() {
+        for url in [
+            "http://192.168.1.20:13378",
+            "http://10.0.0.5",
+            "http://127.0.0.1:13378",
+            "http://100.101.102.103",
+            "http://[fd00::1]",
+            "http://[fe80::1]",
+            "http://localhost:13378",
+            "http://nas:13378",
+            "http://abs.home.arpa",
+            "http://nas.local.",
+        ] {
+            assert!(is_private_url(url), "{} should be private", url);
+        }
+        for url in ["https://abs.example.com", "http://8.8.8.8", "http://100.128.0.1", "http://[2001:db8::1]"] {
+            assert!(!is_private_url(url), "{} should be public", url);
+        }
+    }
+
+    #[test]
+    fn schedule_windows_respect_days_and_wrap_past_midnight() {
+        let window = |days: &[&str], from: &str, to: &str| ScheduleWindow {
+            days: days.iter().map(|day| day.to_string()).collect(),
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        // 2024-06-07 is a Friday.
+        let at = |day: u32, time: &str| {
+            NaiveDateTime::parse_from_str(&format!("2024-06-{:02} {}", day, time), "%Y-%m-%d %H:%M").unwrap()
+        };
+        let work = [window(&["mon", "tue", "wed", "thu", "fri"], "09:00", "17:00")];
+        assert!(is_scheduled_off(&work, at(7, "09:00")).unwrap());
+        assert!(!is_scheduled_off(&work, at(7, "17:00")).unwrap());
+        assert!(!is_scheduled_off(&work, at(8, "12:00")).unwrap());
+
+        // Friday night into Saturday morning, but not Saturday night.
+        let night = [window(&["Fri"], "22:00", "06:00")];
+        assert!(is_scheduled_off(&night, at(7, "23:30")).unwrap());
+        assert!(is_scheduled_off(&night, at(8, "05:59")).unwrap());
+        assert!(!is_scheduled_off(&night, at(8, "23:30")).unwrap());
+        assert!(!is_scheduled_off(&night, at(7, "05:00")).unwrap());
+
+        let weekend = [window(&["sat", "sunday"], "00:00", "00:00")];
+        assert!(is_scheduled_off(&weekend, at(9, "13:00")).unwrap());
+        assert!(!is_scheduled_off(&weekend, at(10, "13:00")).unwrap());
+        assert!(!is_scheduled_off(&[], at(7, "12:00")).unwrap());
+        assert!(is_scheduled_off(&[window(&[], "23:00", "01:00")], at(10, "00:30")).unwrap());
+
+        assert!(is_scheduled_off(&[window(&["weekday"], "09:00", "17:00")], at(7, "12:00")).is_err());
+        assert!(is_scheduled_off(&[window(&[], "9am", "17:00")], at(7, "12:00")).is_err());
+    }
+
+    #[test]
+    fn timeline_start_is_stable_while_playing() {
+        let reported_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let timestamps_after = |secs: f64| {
+            let now = reported_at + Duration::from_secs_f64(secs);
+            let position = extrapolate_position(600.0, reported_at, now, 1.5);
+            timeline_timestamps(now, position, 0.0, 3600.0, 1.5)
+        };
+
+        let (start, end) = timestamps_after(0.0);
+        assert_eq!(start, 1_700_000_000 - 400);
+        assert_eq!(end, 1_700_000_000 + 2000);
+        for secs in [0.4, 15.0, 37.7, 90.0, 1234.5] {
+            assert_eq!(timestamps_after(secs), (start, end));
+        }
+    }
+
+    #[test]
+    fn detect_playing_keeps_playing_through_forward_seek() {
+        let mut stalled = 0;
+        assert!(detect_playing(true, &mut stalled, 100.0, 115.0, 10.0, 2));
+        assert!(detect_playing(true, &mut stalled, 115.0, 900.0, 10.0, 2));
+        assert_eq!(stalled, 0);
+    }
+
+    #[test]
+    fn detect_playing_treats_backward_jump_as_seek() {
+        let mut stalled = 1;
+        assert!(detect_playing(true, &mut stalled, 900.0, 120.0, 10.0, 2));
+        assert_eq!(stalled, 0);
+
+        // A small step back (e.g. another device's slightly older position)
+        // is no progress, not a seek.
+        assert!(detect_playing(true, &mut stalled, 120.0, 117.0, 10.0, 2));
+        assert_eq!(stalled, 1);
+    }
+
+    #[test]
+    fn played_between_ignores_seeks_and_rewinds() {
+        assert_eq!(played_between(100.0, 115.0, 15.0, 1.0, 10.0), 15.0);
+        assert_eq!(played_between(100.0, 130.0, 15.0, 2.0, 10.0), 15.0);
+        assert_eq!(played_between(100.0, 900.0, 15.0, 1.0, 10.0), 25.0);
+        assert_eq!(played_between(100.0, 90.0, 15.0, 1.0, 10.0), 0.0);
+    }
+
+    #[test]
+    fn detect_playing_pauses_after_consecutive_stalled_polls() {
+        let mut stalled = 0;
+        assert!(detect_playing(true, &mut stalled, 100.0, 100.0, 10.0, 3));
+        assert!(detect_playing(true, &mut stalled, 100.0, 100.0, 10.0, 3));
+        assert!(!detect_playing(true, &mut stalled, 100.0, 100.0, 10.0, 3));
+        assert!(!detect_playing(false, &mut stalled, 100.0, 100.0, 10.0, 3));
+        assert!(detect_playing(false, &mut stalled, 100.0, 105.0, 10.0, 3));
+    }
+}
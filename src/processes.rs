@@ -0,0 +1,80 @@
+//! Watching the process table for `suppress_when_running`.
+
+use std::ffi::OsStr;
+use std::time::{Duration, Instant};
+use sysinfo::{ProcessesToUpdate, System};
+
+/// Remembers the last scan so the process list is read at most once per
+/// poll interval, however often the main loop wakes up.
+pub struct ProcessWatch {
+    system: System,
+    checked_at: Option<Instant>,
+    running: Option<String>,
+}
+
+impl Default for ProcessWatch {
+    fn default() -> Self {
+        ProcessWatch {
+            system: System::new(),
+            checked_at: None,
+            running: None,
+        }
+    }
+}
+
+impl ProcessWatch {
+    /// The first of `names` that is running, rescanning when the last scan is
+    /// older than `max_age`.
+    pub fn running(&mut self, names: &[String], max_age: Duration) -> Option<&str> {
+        if names.is_empty() {
+            return None;
+        }
+        if self.checked_at.is_none_or(|checked_at| checked_at.elapsed() >= max_age) {
+            self.system.refresh_processes(ProcessesToUpdate::All, true);
+            let running = self.system.processes().values().map(|process| process.name());
+            self.running = find_running(names, running).map(str::to_string);
+            self.checked_at = Some(Instant::now());
+        }
+        self.running.as_deref()
+    }
+}
+
+/// Matches executable names ignoring case and a trailing ".exe", so
+/// "obs" and "OBS64.exe" style entries work the same on every platform.
+pub(crate) fn find_running<'a, 'p>(
+    names: &'a [String],
+    running: impl Iterator<Item = &'p OsStr>,
+) -> Option<&'a str> {
+    let normalize = |name: &str| {
+        let name = name.to_lowercase();
+        match name.strip_suffix(".exe") {
+            Some(stem) => stem.to_string(),
+            None => name,
+        }
+    };
+    let wanted: Vec<String> = names.iter().map(|name| normalize(name)).collect();
+    for process in running {
+        let process = normalize(&process.to_string_lossy());
+        if let Some(index) = wanted.iter().position(|name| *name == process) {
+            return Some(&names[index]);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_running_ignores_case_and_exe_suffix() {
+        let names = vec!["obs64.exe".to_string(), "Steam".to_string()];
+        let running = |list: &'static [&'static str]| list.iter().map(OsStr::new);
+
+        assert_eq!(find_running(&names, running(&["bash", "OBS64.EXE"])), Some("obs64.exe"));
+        assert_eq!(find_running(&names, running(&["obs64"])), Some("obs64.exe"));
+        assert_eq!(find_running(&names, running(&["steam.exe"])), Some("Steam"));
+        assert_eq!(find_running(&names, running(&["steamwebhelper", "obs"])), None);
+        assert_eq!(find_running(&[], running(&["steam"])), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Optional socket.io connection that wakes the poll loop on Audiobookshelf
+//! playback events.
+
+use futures::FutureExt;
+use log::{info, warn};
+use rust_socketio::asynchronous::{Client as SocketClient, ClientBuilder as SocketClientBuilder};
+use rust_socketio::Payload;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::config::{AccountConfig, Config};
+use crate::error::redact_url;
+
+pub(crate) const SOCKET_RECONNECT_BASE_SECS: u64 = 1;
+pub(crate) const SOCKET_RECONNECT_MAX_SECS: u64 = 60;
+/// Audiobookshelf socket events that mean the listening session changed.
+pub(crate) const SOCKET_WAKE_EVENTS: &[&str] = &["user_item_progress_updated", "user_session_closed"];
+
+pub fn spawn_sockets(config: &Config, wake: &Arc<Notify>) -> Vec<JoinHandle<()>> {
+    if !config.use_websocket.unwrap_or(false) {
+        return Vec::new();
+    }
+    config
+        .accounts
+        .iter()
+        .map(|account| tokio::spawn(run_socket(account.clone(), wake.clone())))
+        .collect()
+}
+
+/// Keeps a socket.io connection to one Audiobookshelf server open, waking the
+/// poll loop whenever the user's session changes. Reconnects with backoff.
+pub(crate) async fn run_socket(account: AccountConfig, wake: Arc<Notify>) {
+    let mut delay = SOCKET_RECONNECT_BASE_SECS;
+    loop {
+        let (closed_tx, mut closed_rx) = mpsc::unbounded_channel();
+        match connect_socket(&account, wake.clone(), closed_tx).await {
+            Ok(socket) => {
+                info!("Listening for Audiobookshelf events from {}", redact_url(&account.audiobookshelf_url));
+                delay = SOCKET_RECONNECT_BASE_SECS;
+                closed_rx.recv().await;
+                let _ = socket.disconnect().await;
+                warn!(
+                    "Lost Audiobookshelf socket connection to {}, falling back to polling",
+                    account.audiobookshelf_url
+                );
+            }
+            Err(e) => {
+                warn!("Couldn't open Audiobookshelf socket to {}: {}", account.audiobookshelf_url, e);
+            }
+        }
+        time::sleep(Duration::from_secs(delay)).await;
+        delay = (delay * 2).min(SOCKET_RECONNECT_MAX_SECS);
+    }
+}
+
+pub(crate) async fn connect_socket(
+    account: &AccountConfig,
+    wake: Arc<Notify>,
+    closed: mpsc::UnboundedSender<()>,
+) -> Result<SocketClient, rust_socketio::Error> {
+    let mut builder = SocketClientBuilder::new(socket_url(&account.audiobookshelf_url)).reconnect(false);
+    for &event in SOCKET_WAKE_EVENTS {
+        let wake = wake.clone();
+        builder = builder.on(event, move |_: Payload, _: SocketClient| {
+            let wake = wake.clone();
+            async move { wake.notify_one() }.boxed()
+        });
+    }
+    for event in ["close", "error"] {
+        let closed = closed.clone();
+        builder = builder.on(event, move |_: Payload, _: SocketClient| {
+            let _ = closed.send(());
+            async {}.boxed()
+        });
+    }
+
+    let socket = builder.connect().await?;
+    socket.emit("auth", account.audiobookshelf_token.as_str()).await?;
+    Ok(socket)
+}
+
+/// The socket.io client only fills in `/socket.io/` for a bare host, so it
+/// is added explicitly to keep servers under a subpath working.
+pub(crate) fn socket_url(base_url: &str) -> String {
+    format!("{}/socket.io/", base_url.trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_url_keeps_the_subpath() {
+        assert_eq!(socket_url("https://example.com/audiobookshelf"), "https://example.com/audiobookshelf/socket.io/");
+        assert_eq!(socket_url("http://localhost:13378/"), "http://localhost:13378/socket.io/");
+    }
+}
@@ -0,0 +1,117 @@
+//! Optional HTTP endpoint for liveness probes (`GET /health`) and a JSON
+//! snapshot of what is being shown (`GET /status`).
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const DEFAULT_STATUS_BIND: &str = "127.0.0.1";
+pub(crate) const MAX_REQUEST_HEAD_LEN: usize = 8 * 1024;
+
+/// What the main loop last saw, served as JSON by `GET /status`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Status {
+    pub current_book: Option<String>,
+    pub playing: bool,
+    pub discord_connected: bool,
+    /// RFC 3339 time of the last poll that reached Audiobookshelf.
+    pub last_successful_poll: Option<String>,
+}
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+/// Binds the status server and serves it in the background. The port is
+/// only read at startup; changing it needs a restart.
+pub async fn spawn_status_server(bind: &str, port: u16, status: SharedStatus) -> std::io::Result<()> {
+    let listener = TcpListener::bind((bind, port)).await?;
+    info!("Serving /health and /status on http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let status = status.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_connection(stream, &status).await {
+                            debug!("Status request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Status server couldn't accept a connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn handle_connection(mut stream: TcpStream, status: &SharedStatus) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD_LEN {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let request_line = head.lines().next().unwrap_or_default();
+    let snapshot = status.lock().unwrap().clone();
+    let (code, reason, content_type, body) = respond(request_line, &snapshot);
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Status code, reason, content type and body for a request line such as
+/// `GET /status HTTP/1.1`.
+pub(crate) fn respond(request_line: &str, status: &Status) -> (u16, &'static str, &'static str, String) {
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+    match (method, path) {
+        ("GET", "/health") => (200, "OK", "text/plain", "ok\n".to_string()),
+        ("GET", "/status") => (
+            200,
+            "OK",
+            "application/json",
+            serde_json::to_string(status).unwrap_or_default(),
+        ),
+        (_, "/health" | "/status") => (405, "Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+        _ => (404, "Not Found", "text/plain", "not found\n".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn respond_routes_health_and_status() {
+        let status = Status {
+            current_book: Some("Dune".to_string()),
+            playing: true,
+            discord_connected: true,
+            last_successful_poll: Some("2024-01-01T00:00:00+00:00".to_string()),
+        };
+
+        assert_eq!(respond("GET /health HTTP/1.1", &status).0, 200);
+        assert_eq!(respond("GET /nope HTTP/1.1", &status).0, 404);
+        assert_eq!(respond("POST /health HTTP/1.1", &status).0, 405);
+
+        let (code, _, content_type, body) = respond("GET /status?pretty HTTP/1.1", &status);
+        assert_eq!((code, content_type), (200, "application/json"));
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["current_book"], "Dune");
+        assert_eq!(body["playing"], true);
+    }
+}
@@ -0,0 +1,101 @@
+//! Checking GitHub for a newer release.
+
+use log::{debug, info, warn};
+use reqwest::Client;
+use semver::Version;
+use serde::Deserialize;
+use std::time::Duration;
+
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const UPDATE_CHECK_TIMEOUT_SECS: u64 = 3;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReleaseInfo {
+    pub(crate) tag_name: String,
+}
+
+/// Checks for a newer release in the background and logs the result, so a
+/// slow or unreachable GitHub never holds up the presence.
+pub fn spawn_update_check(client: &Client) {
+    let client = client.clone();
+    tokio::spawn(async move {
+        match check_for_update(&client).await {
+            Ok(Some(latest_version)) => {
+                info!(
+                    "A new version is available: {}. You're currently running version {}.",
+                    latest_version, CURRENT_VERSION
+                );
+                info!("Please re-run the installer or visit https://github.com/0xGingi/audiobookshelf-discord-rpc/releases to download the latest version.");
+            }
+            Ok(None) => info!("You're running the latest version: {}", CURRENT_VERSION),
+            Err(e) if e.downcast_ref::<reqwest::Error>().is_some_and(reqwest::Error::is_timeout) => warn!(
+                "GitHub didn't answer within {}s, skipping the check for a new version",
+                UPDATE_CHECK_TIMEOUT_SECS
+            ),
+            Err(e) => warn!("Couldn't check for a new version: {}", e),
+        }
+    });
+}
+
+pub async fn check_for_update(client: &Client) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let url = "https://api.github.com/repos/0xGingi/audiobookshelf-discord-rpc/releases/latest";
+    let resp = client
+        .get(url)
+        .header("User-Agent", "Audiobookshelf-Discord-RPC")
+        .timeout(Duration::from_secs(UPDATE_CHECK_TIMEOUT_SECS))
+        .send()
+        .await?;
+
+    if !resp.status().is_success() {
+        return Err(format!("GitHub API request failed with status: {}", resp.status()).into());
+    }
+
+    let release_info: ReleaseInfo = resp.json().await?;
+    Ok(newer_version(CURRENT_VERSION, &release_info.tag_name))
+}
+
+/// Returns the release tag's version when it is strictly newer than
+/// `current`. Installer releases (`installer-v…`) and tags that don't parse
+/// as semver are treated as up to date.
+pub(crate) fn newer_version(current: &str, tag_name: &str) -> Option<String> {
+    let tag = tag_name.trim();
+    if tag.starts_with("installer-") {
+        debug!("Ignoring installer release \"{}\"", tag_name);
+        return None;
+    }
+    let latest = match Version::parse(tag.trim_start_matches(['v', 'V', '.'])) {
+        Ok(latest) => latest,
+        Err(e) => {
+            debug!("Skipping the update check, release tag \"{}\" isn't a version: {}", tag_name, e);
+            return None;
+        }
+    };
+    let current = match Version::parse(current.trim_start_matches(['v', 'V', '.'])) {
+        Ok(current) => current,
+        Err(e) => {
+            debug!("Skipping the update check, can't parse current version \"{}\": {}", current, e);
+            return None;
+        }
+    };
+    (latest > current).then(|| latest.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_version_only_reports_strictly_greater_releases() {
+        assert_eq!(newer_version("1.6.0", "v1.7.0"), Some("1.7.0".to_string()));
+        assert_eq!(newer_version("1.6.0", ".1.6.0"), None);
+        assert_eq!(newer_version("1.6.0", "v1.6.0"), None);
+        assert_eq!(newer_version("1.7.0", "v1.6.0"), None);
+        assert_eq!(newer_version("1.6.0", "v1.7.0-beta.1"), Some("1.7.0-beta.1".to_string()));
+        assert_eq!(newer_version("1.6.0", "1.7.0-rc1"), Some("1.7.0-rc1".to_string()));
+        assert_eq!(newer_version("1.7.0", "1.7.0-rc1"), None);
+        assert_eq!(newer_version("1.7.0-rc1", "v1.7.0"), Some("1.7.0".to_string()));
+        assert_eq!(newer_version("1.6.0", "installer-v2.0.0"), None);
+        assert_eq!(newer_version("1.6.0", "installer-v1.2.0"), None);
+        assert_eq!(newer_version("1.6.0", "latest"), None);
+    }
+}
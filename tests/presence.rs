@@ -0,0 +1,827 @@
+//! Drives `set_activity` against a fake Audiobookshelf server and checks
+//! what would have been sent to Discord.
+
+use audiobookshelf_discord_rpc::abs_client::AbsClient;
+use audiobookshelf_discord_rpc::cache::CoverCache;
+use audiobookshelf_discord_rpc::config::Config;
+use audiobookshelf_discord_rpc::discord::{ActivityUpdate, PresenceSink, PresenceType};
+use audiobookshelf_discord_rpc::error::AppError;
+use audiobookshelf_discord_rpc::presence::{set_activity, PresenceState};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Records every update; `None` is a cleared activity.
+#[derive(Default)]
+struct MockSink {
+    updates: Vec<Option<ActivityUpdate>>,
+    client_ids: Vec<String>,
+}
+
+impl PresenceSink for MockSink {
+    fn set(&mut self, update: &ActivityUpdate) -> Result<(), AppError> {
+        self.updates.push(Some(update.clone()));
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), AppError> {
+        self.updates.push(None);
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Option<Duration> {
+        None
+    }
+
+    fn use_client_id(&mut self, client_id: &str) {
+        self.client_ids.push(client_id.to_string());
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64
+}
+
+fn book_session(item_id: &str, current_time: f64) -> Value {
+    json!({
+        "id": format!("session_{}", item_id),
+        "displayTitle": "The Way of Kings",
+        "displayAuthor": "Brandon Sanderson",
+        "currentTime": current_time,
+        "duration": 3600.0,
+        "playbackRate": 1.0,
+        "updatedAt": now_millis(),
+        "mediaType": "book",
+        "mediaMetadata": {
+            "title": "The Way of Kings",
+            "genres": ["Fantasy"],
+            "narrators": ["Michael Kramer"],
+            "series": [{ "name": "The Stormlight Archive", "sequence": "1" }]
+        },
+        "libraryItemId": item_id
+    })
+}
+
+fn podcast_session(current_time: f64) -> Value {
+    json!({
+        "id": "session_podcast",
+        "displayTitle": "Episode 5: Deep Dive",
+        "displayAuthor": "The Hosts",
+        "currentTime": current_time,
+        "duration": 1800.0,
+        "playbackRate": 1.0,
+        "updatedAt": now_millis(),
+        "mediaType": "podcast",
+        "mediaMetadata": { "title": "The Show", "genres": ["Technology"] },
+        "libraryItemId": "li_podcast",
+        "episodeId": "ep_5"
+    })
+}
+
+fn with_chapters() -> Value {
+    json!({
+        "media": {
+            "chapters": [
+                { "start": 0.0, "end": 100.0, "title": "1" },
+                { "start": 100.0, "end": 200.0, "title": "2" },
+                { "start": 200.0, "end": 3600.0, "title": "3" }
+            ]
+        }
+    })
+}
+
+fn without_chapters() -> Value {
+    json!({ "media": {} })
+}
+
+fn podcast_item() -> Value {
+    json!({
+        "media": {
+            "episodes": [
+                { "id": "ep_4", "season": "2", "episode": "4" },
+                { "id": "ep_5", "season": "2", "episode": "5" }
+            ]
+        }
+    })
+}
+
+fn config(server: &MockServer, extra: Value) -> Config {
+    let mut config = json!({
+        "discord_client_id": "1283070638088650752",
+        "audiobookshelf_url": server.uri(),
+        "accounts": [{ "audiobookshelf_url": server.uri(), "audiobookshelf_token": "token" }],
+        "cover_providers": ["audible"]
+    });
+    config.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(config).unwrap()
+}
+
+/// Serves each sessions response once, in order, repeating the last one.
+async fn mount_sessions(server: &MockServer, sessions: Vec<Value>) {
+    let last = sessions.len() - 1;
+    for (index, session) in sessions.into_iter().enumerate() {
+        let mock = Mock::given(method("GET"))
+            .and(path("/api/me/listening-sessions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sessions": [session] })));
+        let mock = if index < last { mock.up_to_n_times(1) } else { mock };
+        mock.mount(server).await;
+    }
+}
+
+async fn mount_item(server: &MockServer, item_id: &str, item: Value) {
+    Mock::given(method("GET"))
+        .and(path(format!("/api/items/{}", item_id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(item))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/search/covers"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": ["https://covers.example/1.jpg"] })))
+        .mount(server)
+        .await;
+}
+
+async fn poll(config: &Config, polls: usize) -> Vec<Option<ActivityUpdate>> {
+    poll_sink(config, polls).await.updates
+}
+
+async fn poll_sink(config: &Config, polls: usize) -> MockSink {
+    let client = Client::new();
+    let mut sink = MockSink::default();
+    let mut state = PresenceState::default();
+    let mut cover_cache = CoverCache::default();
+    for _ in 0..polls {
+        set_activity(&client, config, &mut sink, &mut state, &mut cover_cache)
+            .await
+            .unwrap();
+    }
+    sink
+}
+
+/// Sessions are shown as playing from the third poll: the first one only
+/// records the position and the second one switches to the new book.
+fn last_activity(updates: &[Option<ActivityUpdate>]) -> &ActivityUpdate {
+    updates.last().unwrap().as_ref().expect("activity was cleared")
+}
+
+#[tokio::test]
+async fn book_with_chapters_shows_current_chapter() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let config = config(&server, json!({ "show_chapters": true, "show_series": true }));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+
+    assert_eq!(activity.details, "The Way of Kings");
+    assert_eq!(activity.state, "Brandon Sanderson • The Stormlight Archive #1");
+    assert_eq!(activity.large_text, "Chapter 2");
+    assert_eq!(activity.chapter.as_deref(), Some("Chapter 2"));
+    assert_eq!(activity.large_image.as_deref(), Some("https://covers.example/1.jpg"));
+
+    let (start, end) = (activity.start.unwrap(), activity.end.unwrap());
+    assert!((start - (now_secs() - 160)).abs() <= 1, "start {} is not 160s ago", start);
+    assert!((end - start - 3600).abs() <= 1, "timeline spans {}s", end - start);
+}
+
+#[tokio::test]
+async fn show_chapters_modes_pick_the_large_text() {
+    for (mode, expected) in [("both", "Chapter 2 • Fantasy"), ("genre", "Fantasy"), ("none", "")] {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", with_chapters()).await;
+        let config = config(&server, json!({ "show_chapters": mode }));
+
+        let updates = poll(&config, 3).await;
+        assert_eq!(last_activity(&updates).large_text, expected, "show_chapters: {}", mode);
+    }
+}
+
+#[tokio::test]
+async fn chapter_time_left_is_added_inside_the_chapter() {
+    for (duration, expected) in [(3600.0, "Chapter 2 (0:40 left)"), (150.0, "Chapter 2")] {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0]
+            .map(|time| {
+                let mut session = book_session("li_book", time);
+                session["duration"] = json!(duration);
+                session
+            })
+            .to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", with_chapters()).await;
+        let config = config(&server, json!({ "show_chapters": "chapter", "show_chapter_time_left": true }));
+
+        let updates = poll(&config, 3).await;
+        assert_eq!(last_activity(&updates).large_text, expected, "duration {}", duration);
+    }
+}
+
+#[tokio::test]
+async fn show_series_uses_the_first_series_and_skips_standalone_books() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0]
+        .map(|time| {
+            let mut session = book_session("li_book", time);
+            session["mediaMetadata"]["series"] = json!([
+                { "name": "The Wheel of Time", "sequence": "3" },
+                { "name": "Cosmere", "sequence": null }
+            ]);
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", without_chapters()).await;
+    let updates = poll(&config(&server, json!({ "show_series": true })), 3).await;
+    assert_eq!(last_activity(&updates).state, "Brandon Sanderson • The Wheel of Time #3");
+
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0]
+        .map(|time| {
+            let mut session = book_session("li_book", time);
+            session["mediaMetadata"]["series"] = json!([]);
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", without_chapters()).await;
+    let updates = poll(&config(&server, json!({ "show_series": true })), 3).await;
+    assert_eq!(last_activity(&updates).state, "Brandon Sanderson");
+}
+
+#[tokio::test]
+async fn hidden_author_leaves_only_the_series() {
+    for (extra, expected) in [
+        (json!({ "show_author": false, "show_series": true }), "The Stormlight Archive #1"),
+        (json!({ "show_author": false }), ""),
+    ] {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", without_chapters()).await;
+
+        let updates = poll(&config(&server, extra), 3).await;
+        assert_eq!(last_activity(&updates).state, expected);
+    }
+}
+
+#[tokio::test]
+async fn library_client_ids_pick_the_discord_app() {
+    for (library_id, expected) in [("lib_podcasts", "42"), ("lib_books", "1283070638088650752")] {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0]
+            .map(|time| {
+                let mut session = book_session("li_book", time);
+                session["libraryId"] = json!(library_id);
+                session
+            })
+            .to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", without_chapters()).await;
+        let config = config(&server, json!({ "library_client_ids": { "lib_podcasts": "42" } }));
+
+        let sink = poll_sink(&config, 3).await;
+        assert_eq!(sink.client_ids.last().map(String::as_str), Some(expected));
+    }
+}
+
+#[tokio::test]
+async fn blocklisted_sessions_never_reach_the_cover_search() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", without_chapters()).await;
+    let config = config(&server, json!({ "blocklist": { "patterns": ["stormlight archive"] } }));
+
+    let updates = poll(&config, 3).await;
+    assert!(updates.iter().all(Option::is_none), "blocked book was shown: {:?}", updates);
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests.iter().all(|request| !request.url.path().starts_with("/api/search")));
+}
+
+#[tokio::test]
+async fn ignored_libraries_and_items_are_cleared() {
+    for (extra, shown) in [
+        (json!({ "ignored_library_ids": ["lib_personal"] }), false),
+        (json!({ "ignored_item_ids": ["li_book"] }), false),
+        (json!({ "ignored_library_ids": ["lib_other"], "ignored_item_ids": ["li_other"] }), true),
+    ] {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0]
+            .map(|time| {
+                let mut session = book_session("li_book", time);
+                session["libraryId"] = json!("lib_personal");
+                session
+            })
+            .to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", without_chapters()).await;
+
+        let updates = poll(&config(&server, extra.clone()), 3).await;
+        assert_eq!(updates.last().unwrap().is_some(), shown, "{}", extra);
+    }
+}
+
+#[tokio::test]
+async fn ignored_session_leaves_the_other_one_shown() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0]
+        .map(|time| {
+            let mut personal = book_session("li_personal", time);
+            personal["id"] = json!("session_personal");
+            personal["displayTitle"] = json!("Private Notes");
+            personal["libraryId"] = json!("lib_personal");
+            personal["updatedAt"] = json!(now_millis() + 1000);
+            json!({ "sessions": [personal, book_session("li_book", time)] })
+        })
+        .to_vec();
+    let last = sessions.len() - 1;
+    for (index, response) in sessions.into_iter().enumerate() {
+        let mock = Mock::given(method("GET"))
+            .and(path("/api/me/listening-sessions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(response));
+        let mock = if index < last { mock.up_to_n_times(1) } else { mock };
+        mock.mount(&server).await;
+    }
+    mount_item(&server, "li_book", without_chapters()).await;
+    let config = config(&server, json!({ "ignored_library_ids": ["lib_personal"] }));
+
+    let updates = poll(&config, 3).await;
+    assert_eq!(last_activity(&updates).details, "The Way of Kings");
+    assert_eq!(item_requests(&server, "li_personal").await, 0);
+}
+
+#[tokio::test]
+async fn privacy_mode_shows_only_a_placeholder() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", without_chapters()).await;
+    let config = config(&server, json!({ "privacy_mode": true }));
+
+    let updates = poll(&config, 3).await;
+    assert_eq!(
+        last_activity(&updates),
+        &ActivityUpdate {
+            details: "Listening to an audiobook".to_string(),
+            ..ActivityUpdate::default()
+        }
+    );
+}
+
+#[tokio::test]
+async fn schedule_holds_the_presence_and_resumes_playing() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0, 190.0, 220.0, 250.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", without_chapters()).await;
+    let unscheduled = config(&server, json!({}));
+    let scheduled = config(&server, json!({ "schedule": [{ "from": "00:00", "to": "00:00" }] }));
+
+    let client = Client::new();
+    let mut sink = MockSink::default();
+    let mut state = PresenceState::default();
+    let mut cover_cache = CoverCache::default();
+    for config in [&unscheduled, &unscheduled, &unscheduled, &scheduled, &scheduled] {
+        set_activity(&client, config, &mut sink, &mut state, &mut cover_cache)
+            .await
+            .unwrap();
+    }
+    assert!(sink.updates[2].is_some());
+    assert!(sink.updates[3..].iter().all(Option::is_none), "shown during the schedule: {:?}", sink.updates);
+    assert_eq!(item_requests(&server, "li_book").await, 1);
+
+    set_activity(&client, &unscheduled, &mut sink, &mut state, &mut cover_cache)
+        .await
+        .unwrap();
+    let activity = last_activity(&sink.updates);
+    assert_eq!(activity.small_text, "Playing");
+    assert!(activity.end.is_some());
+}
+
+async fn item_requests(server: &MockServer, item_id: &str) -> usize {
+    let requests = server.received_requests().await.unwrap();
+    let item_path = format!("/api/items/{}", item_id);
+    requests.iter().filter(|request| request.url.path() == item_path).count()
+}
+
+#[tokio::test]
+async fn library_item_is_fetched_once_per_book() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0, 190.0]
+        .map(|time| {
+            let mut session = book_session("li_book", time);
+            // Re-encoded before the last poll.
+            if time > 160.0 {
+                session["duration"] = json!(3000.0);
+            }
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let chapters = config(&server, json!({ "show_chapters": true }));
+
+    let client = Client::new();
+    let mut sink = MockSink::default();
+    let mut state = PresenceState::default();
+    let mut cover_cache = CoverCache::default();
+    for expected_requests in [0, 1, 1, 2] {
+        set_activity(&client, &chapters, &mut sink, &mut state, &mut cover_cache)
+            .await
+            .unwrap();
+        assert_eq!(item_requests(&server, "li_book").await, expected_requests);
+    }
+    assert_eq!(last_activity(&sink.updates).large_text, "Chapter 2");
+
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    poll(&config(&server, json!({ "library_item_ttl_secs": 0 })), 3).await;
+    assert_eq!(item_requests(&server, "li_book").await, 2);
+}
+
+#[tokio::test]
+async fn min_play_seconds_waits_for_real_listening() {
+    let server = MockServer::start().await;
+    // The jump to 900 is a seek and only counts up to the seek threshold.
+    let sessions = [100.0, 105.0, 900.0, 905.0, 910.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let config = config(&server, json!({ "min_play_seconds": 20, "seek_threshold_seconds": 10 }));
+
+    let updates = poll(&config, 5).await;
+
+    assert_eq!(updates[..4], [None, None, None, None]);
+    assert_eq!(last_activity(&updates).details, "The Way of Kings");
+}
+
+#[tokio::test]
+async fn abs_requests_retry_server_errors_but_not_missing_items() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/me/listening-sessions"))
+        .respond_with(ResponseTemplate::new(502))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    mount_sessions(&server, vec![book_session("li_book", 100.0)]).await;
+    Mock::given(method("GET"))
+        .and(path("/api/items/li_gone"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let abs = AbsClient::new(&Client::new(), &server.uri(), "token", Duration::from_secs(5));
+
+    let sessions = abs.latest_sessions(1).await.unwrap();
+    assert_eq!(sessions[0].libraryItemId, "li_book");
+    assert!(matches!(abs.library_item("li_gone").await, Err(AppError::AbsHttp { status }) if status == 404));
+    assert_eq!(item_requests(&server, "li_gone").await, 1);
+}
+
+#[tokio::test]
+async fn rejected_token_clears_the_presence_without_retrying() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/me/listening-sessions"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    let config = config(&server, json!({}));
+    let client = Client::new();
+    let mut sink = MockSink::default();
+    let mut state = PresenceState::default();
+    let mut cover_cache = CoverCache::default();
+
+    for _ in 0..2 {
+        set_activity(&client, &config, &mut sink, &mut state, &mut cover_cache)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(sink.updates, vec![None, None]);
+    assert!(state.timing.all_auth_failed());
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    let error = AppError::AbsHttp { status: reqwest::StatusCode::UNAUTHORIZED };
+    assert!(error.to_string().contains("Settings > Users"), "{}", error);
+}
+
+#[tokio::test]
+async fn failing_server_keeps_the_activity_for_one_poll() {
+    let server = MockServer::start().await;
+    for time in [100.0, 130.0, 160.0] {
+        let mut session = book_session("li_book", time);
+        // Never stale, even with `stale_session_secs` at 0.
+        session["updatedAt"] = json!(now_millis() + 60_000);
+        Mock::given(method("GET"))
+            .and(path("/api/me/listening-sessions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sessions": [session] })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path("/api/me/listening-sessions"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let config = config(&server, json!({ "stale_session_secs": 0 }));
+
+    let client = Client::new();
+    let mut sink = MockSink::default();
+    let mut state = PresenceState::default();
+    let mut cover_cache = CoverCache::default();
+    let mut results = Vec::new();
+    for _ in 0..5 {
+        results.push(set_activity(&client, &config, &mut sink, &mut state, &mut cover_cache).await);
+    }
+
+    assert!(results[..3].iter().all(Result::is_ok));
+    assert!(results[3..].iter().all(Result::is_err));
+
+    assert_eq!(sink.updates.len(), 4);
+    assert!(sink.updates[2].is_some());
+    assert!(sink.updates[3].is_none());
+}
+
+#[tokio::test]
+async fn missing_library_item_still_shows_the_session() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_gone", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    Mock::given(method("GET"))
+        .and(path("/api/items/li_gone"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/search/covers"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "results": ["https://covers.example/1.jpg"] })))
+        .mount(&server)
+        .await;
+    let config = config(&server, json!({ "show_chapters": true }));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+    assert_eq!(activity.details, "The Way of Kings");
+    assert_eq!(activity.state, "Brandon Sanderson");
+    assert_eq!(activity.large_text, "Fantasy");
+    assert_eq!(activity.large_image.as_deref(), Some("https://covers.example/1.jpg"));
+    assert!(activity.end.is_some());
+}
+
+#[tokio::test]
+async fn chapter_timestamp_mode_spans_the_chapter() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let config = config(&server, json!({ "timestamp_mode": "chapter" }));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+
+    let (start, end) = (activity.start.unwrap(), activity.end.unwrap());
+    assert!((start - (now_secs() - 60)).abs() <= 1, "start {} is not 60s ago", start);
+    assert!((end - start - 100).abs() <= 1, "timeline spans {}s", end - start);
+}
+
+#[tokio::test]
+async fn playback_speed_override_shortens_the_timeline() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let config = config(&server, json!({ "playback_speed": 1.5 }));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+
+    let (start, end) = (activity.start.unwrap(), activity.end.unwrap());
+    assert!((end - now_secs() - 2293).abs() <= 1, "{}s left instead of 2293s", end - now_secs());
+    assert!((end - start - 2400).abs() <= 1, "timeline spans {}s", end - start);
+}
+
+#[tokio::test]
+async fn chapterless_book_falls_back_to_genre() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_plain", time)).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_plain", without_chapters()).await;
+    let config = config(&server, json!({ "show_chapters": true }));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+
+    assert_eq!(activity.large_text, "Fantasy");
+    assert_eq!(activity.chapter, None);
+    assert_eq!(activity.state, "Brandon Sanderson");
+}
+
+#[tokio::test]
+async fn podcast_episode_fills_season_and_episode() {
+    let server = MockServer::start().await;
+    let sessions = [10.0, 40.0, 70.0].map(podcast_session).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_podcast", podcast_item()).await;
+    let config = config(&server, json!({ "state_template": "{podcast_title} S{season}E{episode}" }));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+
+    assert_eq!(activity.details, "Episode 5: Deep Dive");
+    assert_eq!(activity.state, "The Show S2E5");
+    assert_eq!(activity.large_text, "Technology");
+    // A local server's cover can't be shown and the book search is skipped.
+    assert_eq!(activity.large_image, None);
+}
+
+#[tokio::test]
+async fn activity_type_is_sent_with_the_update() {
+    let cases = [
+        (json!({}), PresenceType::Listening),
+        (json!({ "activity_type": "Watching" }), PresenceType::Watching),
+        (json!({ "activity_type": "dancing" }), PresenceType::Listening),
+    ];
+    for (overrides, expected) in cases {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", without_chapters()).await;
+        let updates = poll(&config(&server, overrides), 3).await;
+        assert_eq!(last_activity(&updates).activity_type, expected);
+    }
+}
+
+#[tokio::test]
+async fn narrator_name_is_shown_for_books_but_not_podcasts() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0]
+        .map(|time| {
+            let mut session = book_session("li_book", time);
+            session["mediaMetadata"]["narrators"] = json!([]);
+            session["mediaMetadata"]["narratorName"] = json!("Michael Kramer, Kate Reading");
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", without_chapters()).await;
+    let updates = poll(&config(&server, json!({ "show_narrator": true })), 3).await;
+    assert_eq!(
+        last_activity(&updates).state,
+        "Brandon Sanderson • read by Michael Kramer, Kate Reading"
+    );
+
+    let server = MockServer::start().await;
+    let sessions = [10.0, 40.0, 70.0]
+        .map(|time| {
+            let mut session = podcast_session(time);
+            session["mediaMetadata"]["narrators"] = json!(["Someone"]);
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_podcast", podcast_item()).await;
+    let config = config(&server, json!({ "show_narrator": true, "narrator_display": "replace" }));
+    let updates = poll(&config, 3).await;
+    assert_eq!(last_activity(&updates).state, "The Hosts");
+}
+
+#[tokio::test]
+async fn state_content_picks_author_or_narrator() {
+    let cases = [
+        (json!({ "state_content": "author_and_narrator" }), "Brandon Sanderson • read by Michael Kramer"),
+        (json!({ "state_content": "narrator", "show_author": true }), "Narrated by Michael Kramer"),
+        (json!({ "show_narrator": true, "books": { "state_content": "author" } }), "Brandon Sanderson"),
+    ];
+    for (overrides, expected) in cases {
+        let server = MockServer::start().await;
+        let sessions = [100.0, 130.0, 160.0].map(|time| book_session("li_book", time)).to_vec();
+        mount_sessions(&server, sessions).await;
+        mount_item(&server, "li_book", without_chapters()).await;
+        let updates = poll(&config(&server, overrides), 3).await;
+        assert_eq!(last_activity(&updates).state, expected);
+    }
+}
+
+#[tokio::test]
+async fn disabled_podcasts_are_never_shown() {
+    let server = MockServer::start().await;
+    let sessions = [10.0, 40.0, 70.0].map(podcast_session).to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_podcast", podcast_item()).await;
+    let config = config(&server, json!({ "podcasts": { "enabled": false } }));
+
+    let updates = poll(&config, 3).await;
+    assert!(updates.iter().all(Option::is_none), "podcast was shown: {:?}", updates);
+}
+
+#[tokio::test]
+async fn zero_duration_episode_has_no_timestamps() {
+    let server = MockServer::start().await;
+    let sessions = [10.0, 40.0, 70.0]
+        .map(|time| {
+            let mut session = podcast_session(time);
+            session["duration"] = json!(0.0);
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_podcast", podcast_item()).await;
+    let config = config(&server, json!({}));
+
+    let updates = poll(&config, 3).await;
+    let activity = last_activity(&updates);
+
+    assert_eq!(activity.details, "Episode 5: Deep Dive");
+    assert_eq!(activity.state, "The Hosts");
+    assert_eq!((activity.start, activity.end), (None, None));
+}
+
+#[tokio::test]
+async fn stale_session_clears_activity() {
+    let server = MockServer::start().await;
+    let sessions = [100.0, 130.0, 160.0]
+        .map(|time| {
+            let mut session = book_session("li_book", time);
+            session["updatedAt"] = json!(now_millis() - 60 * 60 * 1000);
+            session
+        })
+        .to_vec();
+    mount_sessions(&server, sessions).await;
+    mount_item(&server, "li_book", with_chapters()).await;
+    let config = config(&server, json!({}));
+
+    let updates = poll(&config, 3).await;
+
+    assert_eq!(updates.len(), 3);
+    assert!(updates.iter().all(Option::is_none), "stale session was shown: {:?}", updates);
+}
+
+#[tokio::test]
+async fn cover_bytes_requests_the_item_cover() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/items/li_book/cover"))
+        .and(query_param("width", "400"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"jpeg".to_vec()))
+        .mount(&server)
+        .await;
+
+    let abs = AbsClient::new(&Client::new(), &format!("{}/", server.uri()), "token", Duration::from_secs(5));
+    assert_eq!(abs.cover_bytes("li_book", 400).await.unwrap(), b"jpeg");
+}
+
+#[tokio::test]
+async fn cover_reachability_is_checked_with_head() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path("/api/items/li_book/cover"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let abs = AbsClient::new(&Client::new(), &server.uri(), "token", Duration::from_secs(5));
+    assert!(abs.is_publicly_reachable(&format!("{}/api/items/li_book/cover", server.uri())).await);
+    assert!(!abs.is_publicly_reachable(&format!("{}/api/items/li_other/cover", server.uri())).await);
+}
+
+#[tokio::test]
+async fn me_returns_the_token_owner() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": "usr_1", "username": "reader" })))
+        .mount(&server)
+        .await;
+
+    let abs = AbsClient::new(&Client::new(), &server.uri(), "token", Duration::from_secs(5));
+    assert_eq!(abs.me().await.unwrap().username, "reader");
+}
+
+#[tokio::test]
+async fn subpath_with_trailing_slash_builds_single_slash_urls() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/audiobookshelf/api/me/listening-sessions"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sessions": [book_session("li_book", 10.0)] })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let base_url = format!("{}/audiobookshelf/", server.uri());
+    let abs = AbsClient::new(&Client::new(), &base_url, "token", Duration::from_secs(5));
+    let sessions = abs.latest_sessions(10).await.unwrap();
+    assert_eq!(sessions[0].libraryItemId, "li_book");
+}